@@ -0,0 +1,476 @@
+//! How the bots pick their actions.
+//!
+//! Small positions are solved exactly (see [`crate::solver`]) if that fits in half the [`Budget`].
+//! Everything else is estimated with [`Mcts`] for as long as the rest of the budget allows.
+//!
+//! With the `parallel` feature, native builds run the MCTS on all cores, see [`crate::parallel`].
+
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::game;
+use crate::mcts::{Action, Game, GameState, Mcts};
+use crate::solver::Solver;
+
+/// The random number generator of the AI.
+///
+/// Unlike `SmallRng`, it is the same generator on every platform,
+/// so a seed gives the same numbers on the web as in a native test.
+pub type AiRng = rand_pcg::Pcg32;
+
+/// Positions with at most this many free cells are solved exactly…
+const MAX_FREE_CELLS_TO_SOLVE: usize = 16;
+
+/// …unless that takes more than this many positions, or more than half the budget,
+/// in which case we fall back to MCTS for the rest of it.
+const MAX_NODES_TO_SOLVE: usize = 50_000;
+
+/// Roughly how many positions the solver visits in the time of one MCTS iteration.
+const SOLVER_NODES_PER_ITERATION: usize = 5;
+
+/// How long the AI may think about each action.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Budget {
+    /// A fixed number of MCTS iterations.
+    /// With a seed this picks the same action every time, see [`ai_action_seeded`].
+    Iterations(usize),
+
+    /// A fixed number of seconds.
+    Seconds(f64),
+
+    /// Up to this many seconds on an empty board, and less as the game goes on:
+    /// in proportion to how many cells are still free and how many actions there are to choose from.
+    Scaled(f64),
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget::Seconds(1.0)
+    }
+}
+
+/// For [`Budget::Scaled`]: how many of the `seconds` to use in this position.
+fn scaled_seconds(seconds: f64, state: &GameState, num_actions: usize) -> f64 {
+    let num_cells = state.board.coords().count() as f64;
+    let free = state.board.num_free_cells() as f64 / num_cells;
+    let choices = (num_actions as f64 / num_cells).min(1.0);
+    seconds * (0.5 * (free + choices)).max(0.1)
+}
+
+/// The exact best action, if the position is small enough to solve with at most half the budget.
+/// Otherwise what is left of the budget.
+fn solve_within(
+    state: &GameState,
+    budget: Budget,
+    num_actions: usize,
+) -> Result<Option<Action>, Budget> {
+    if state.board.num_free_cells() > MAX_FREE_CELLS_TO_SOLVE {
+        return Err(budget);
+    }
+    match budget {
+        Budget::Iterations(iterations) => {
+            let max_iterations = iterations / 2;
+            let max_nodes = MAX_NODES_TO_SOLVE.min(max_iterations * SOLVER_NODES_PER_ITERATION);
+            let mut solver = Solver::new().with_max_nodes(max_nodes);
+            match solver.solve(state) {
+                Some(solution) => Ok(solution.best_action),
+                None => Err(Budget::Iterations(iterations - max_iterations)),
+            }
+        }
+        Budget::Seconds(seconds) | Budget::Scaled(seconds) => {
+            let seconds = match budget {
+                Budget::Scaled(_) => scaled_seconds(seconds, state, num_actions),
+                _ => seconds,
+            };
+            let start = now_sec();
+            let mut solver = Solver::new()
+                .with_max_nodes(MAX_NODES_TO_SOLVE)
+                .with_deadline(start + seconds / 2.0);
+            match solver.solve(state) {
+                Some(solution) => Ok(solution.best_action),
+                None => Err(Budget::Seconds((seconds - (now_sec() - start)).max(0.0))),
+            }
+        }
+    }
+}
+
+/// The most explored action, or any action if the search didn't get far enough to have one.
+fn best_action(mcts: &Mcts, state: &GameState) -> Option<Action> {
+    mcts.best_action()
+        .cloned()
+        .or_else(|| state.available_actions().first().copied())
+}
+
+/// Let the AI pick the next action, thinking for as long as the budget allows.
+/// `None` if the game is over.
+///
+/// Stops early once more thinking could not change its mind.
+pub fn think(state: GameState, budget: Budget, rng: &mut AiRng) -> Option<Action> {
+    think_in(&mut Mcts::new(state), budget, rng)
+}
+
+/// Like [`think`], but carry on with a search that may already have been going for a while.
+pub fn think_in(mcts: &mut Mcts, budget: Budget, rng: &mut AiRng) -> Option<Action> {
+    think_with(mcts, budget, rng, search_on_all_cores)
+}
+
+fn think_with(
+    mcts: &mut Mcts,
+    budget: Budget,
+    rng: &mut AiRng,
+    search: fn(&mut Mcts, Budget, usize, &mut AiRng),
+) -> Option<Action> {
+    let state = mcts.state().clone();
+    let actions = state.available_actions();
+    if actions.len() <= 1 {
+        return actions.first().copied();
+    }
+    let budget = match solve_within(&state, budget, actions.len()) {
+        Ok(action) => return action,
+        Err(budget) => budget,
+    };
+
+    search(mcts, budget, actions.len(), rng);
+
+    best_action(mcts, &state)
+}
+
+/// Grow the tree on all cores if we can (see [`crate::parallel`]), or else on this thread.
+fn search_on_all_cores(mcts: &mut Mcts, budget: Budget, num_actions: usize, rng: &mut AiRng) {
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    crate::parallel::search(mcts, budget, num_actions, rng);
+
+    #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+    search(mcts, budget, num_actions, rng);
+}
+
+/// Grow the tree for as long as the budget allows, or until more thinking could not change
+/// which action is best. `num_actions` is how many actions there are to choose from.
+pub fn search(mcts: &mut Mcts, budget: Budget, num_actions: usize, rng: &mut AiRng) {
+    match budget {
+        Budget::Iterations(iterations) => {
+            for done in 0..iterations {
+                if mcts.is_decided(iterations - done) {
+                    break;
+                }
+                mcts.iterate(rng);
+            }
+        }
+        Budget::Seconds(seconds) => think_for(mcts, rng, seconds),
+        Budget::Scaled(seconds) => {
+            let seconds = scaled_seconds(seconds, mcts.state(), num_actions);
+            think_for(mcts, rng, seconds)
+        }
+    }
+}
+
+/// Seconds since some point in the past.
+#[cfg(target_arch = "wasm32")]
+pub fn now_sec() -> f64 {
+    egui_web::now_sec()
+}
+
+/// Seconds since some point in the past.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_sec() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.as_secs_f64())
+}
+
+fn think_for(mcts: &mut Mcts, rng: &mut AiRng, think_time: f64) {
+    let start = now_sec();
+    let iterations_before = mcts.num_iterations();
+    loop {
+        mcts.iterate(rng);
+        let elapsed = now_sec() - start;
+        if elapsed >= think_time {
+            break;
+        }
+        // Assume we keep up the pace so far:
+        let iterations = mcts.num_iterations() - iterations_before;
+        let iterations_per_second = iterations as f64 / elapsed.max(1e-6);
+        let remaining = ((think_time - elapsed) * iterations_per_second) as usize;
+        if mcts.is_decided(remaining) {
+            break;
+        }
+    }
+}
+
+/// Let the AI think for a second and pick the next action, or `None` if the game is over.
+pub fn ai_action(state: GameState) -> Option<Action> {
+    think(state, Budget::default(), &mut AiRng::from_entropy())
+}
+
+/// Let the AI pick the next action after at most `iterations` MCTS iterations,
+/// using `seed` for all its randomness. `None` if the game is over.
+///
+/// The same state, seed and number of iterations give the same action on every platform,
+/// so a move a bot made can be replayed exactly.
+///
+/// For the same reason this always searches on a single thread.
+pub fn ai_action_seeded(state: GameState, seed: u64, iterations: usize) -> Option<Action> {
+    think_with(
+        &mut Mcts::new(state),
+        Budget::Iterations(iterations),
+        &mut AiRng::seed_from_u64(seed),
+        search,
+    )
+}
+
+/// A bot that remembers its search tree between the moves of a game,
+/// so that each search picks up where the last one left off
+/// instead of throwing away everything it learned.
+#[derive(Clone, Default)]
+pub struct Ai {
+    /// The tree, and the actions of the game leading up to its root.
+    tree: Option<(Vec<Action>, Mcts)>,
+}
+
+impl Ai {
+    /// Pick the next action in the game, or `None` if it is over.
+    ///
+    /// If the game has moved on since the last time, the tree follows the actions played since.
+    /// If it has gone somewhere else (e.g. by undo or a new game), we start over.
+    pub fn think(&mut self, game: &game::Game, budget: Budget, rng: &mut AiRng) -> Option<Action> {
+        if game.is_game_over() {
+            self.tree = None;
+            return None;
+        }
+        let history = game.history();
+        let state = game.mcts_state();
+        let mut mcts = match self.tree.take() {
+            Some((tree_history, mut mcts)) if history.starts_with(&tree_history) => {
+                for action in &history[tree_history.len()..] {
+                    mcts.advance(action);
+                }
+                mcts
+            }
+            _ => Mcts::new(state.clone()),
+        };
+        if *mcts.state() != state {
+            // E.g. the same actions in another game:
+            mcts = Mcts::new(state);
+        }
+        let action = think_in(&mut mcts, budget, rng);
+        self.tree = Some((history.to_vec(), mcts));
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hobogo::{Board, Coord};
+
+    fn empty_state(width: i32, height: i32) -> GameState {
+        GameState {
+            next_player: 0,
+            board: Board::new(width, height, 2),
+            num_actions: 0,
+            swappable: None,
+        }
+    }
+
+    #[test]
+    fn solving_stays_within_the_budget() {
+        // Few enough free cells to try solving, but too many to finish within these budgets:
+        let state = empty_state(4, 4);
+        let num_actions = state.available_actions().len();
+        assert_eq!(
+            solve_within(&state, Budget::Iterations(100), num_actions),
+            Err(Budget::Iterations(50))
+        );
+        // The solver gives up as soon as it first looks at the clock:
+        for &budget in &[Budget::Seconds(0.0), Budget::Scaled(0.0)] {
+            assert_eq!(
+                solve_within(&state, budget, num_actions),
+                Err(Budget::Seconds(0.0))
+            );
+        }
+
+        // Too big to even try:
+        let state = empty_state(5, 4);
+        let budget = Budget::Scaled(1.0);
+        assert_eq!(solve_within(&state, budget, 20), Err(budget));
+
+        let state = empty_state(3, 1);
+        assert_eq!(
+            solve_within(&state, Budget::Iterations(100), 3),
+            Ok(Some(Action::Move(Coord::new(1, 0))))
+        );
+    }
+
+    #[test]
+    fn scaled_budgets_shrink_as_the_game_goes_on() {
+        let mut state = empty_state(5, 5);
+        assert_eq!(scaled_seconds(2.0, &state, 25), 2.0);
+        for x in 0..5 {
+            for y in 0..4 {
+                state.board.set(Coord::new(x, y), 0);
+            }
+        }
+        // 5 of 25 cells are free, and 3 of 25 are actions:
+        assert!((scaled_seconds(2.0, &state, 3) - 0.32).abs() < 1e-9);
+        // But never less than a tenth:
+        for x in 0..4 {
+            state.board.set(Coord::new(x, 4), 0);
+        }
+        assert!((scaled_seconds(2.0, &state, 1) - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn search_stops_once_decided() {
+        // B1 wins, and anything else loses, so B1 soon gets so many visits that nothing can catch up:
+        let mut mcts = Mcts::new(empty_state(3, 1));
+        search(
+            &mut mcts,
+            Budget::Iterations(1_000),
+            3,
+            &mut AiRng::seed_from_u64(0),
+        );
+        assert!(mcts.num_iterations() < 1_000, "{}", mcts.num_iterations());
+        assert!(mcts.is_decided(1_000 - mcts.num_iterations()));
+        assert_eq!(mcts.best_action(), Some(&Action::Move(Coord::new(1, 0))));
+
+        // Nothing is decided this early on an empty board:
+        let mut mcts = Mcts::new(empty_state(7, 7));
+        search(
+            &mut mcts,
+            Budget::Iterations(200),
+            49,
+            &mut AiRng::seed_from_u64(0),
+        );
+        assert_eq!(mcts.num_iterations(), 200);
+    }
+
+    /// If this fails, the same seed now gives other actions than before, so recorded bot games
+    /// no longer replay the same. Only update the expected actions if that is intended.
+    ///
+    /// The boards are big enough that these go through the MCTS (and so [`crate::mcts`]'s
+    /// portable logarithm), not the solver.
+    #[test]
+    fn seeded_actions_are_pinned() {
+        let board = Board::new(7, 7, 2);
+        let moves: Vec<String> = (0..4)
+            .map(|seed| board.ai_move_seeded(0, seed, 100).unwrap().to_string())
+            .collect();
+        assert_eq!(moves, ["A6", "B5", "C3", "D4"]);
+
+        let mut game = crate::game::Game::new(Board::new(6, 6, 3), 1);
+        let mut actions = vec![];
+        for seed in 0..4 {
+            let action = game.ai_action_seeded(seed, 50).unwrap();
+            actions.push(action.to_string());
+            game.play(action).unwrap();
+        }
+        assert_eq!(actions, ["C4", "F2", "D1", "D6"]);
+    }
+
+    #[test]
+    fn ai_reuses_its_tree() {
+        let mut game = crate::game::Game::new(Board::new(7, 7, 2), 0);
+        let mut ai = Ai::default();
+        let mut rng = AiRng::seed_from_u64(0);
+        let budget = Budget::Iterations(300);
+        let num_iterations = |ai: &Ai| ai.tree.as_ref().unwrap().1.num_iterations();
+
+        let first = ai.think(&game, budget, &mut rng).unwrap();
+        let searched = num_iterations(&ai);
+        game.play(first).unwrap();
+        // The reply the tree expects, so that it has looked at what comes after:
+        let mut tree = ai.tree.clone().unwrap().1;
+        tree.advance(&first);
+        let reply = *tree.best_action().unwrap();
+        game.play(reply).unwrap();
+
+        // The tree follows both moves, and keeps what it learned about the position after them:
+        tree.advance(&reply);
+        let kept = tree.num_iterations();
+        ai.think(&game, budget, &mut rng).unwrap();
+        assert!(kept > 0);
+        assert!(
+            num_iterations(&ai) > kept,
+            "{} {}",
+            num_iterations(&ai),
+            kept
+        );
+        assert!(num_iterations(&ai) <= kept + 300);
+        assert!(searched <= 300);
+
+        // After an undo, the game is no longer ahead of the tree, so it starts over:
+        game.unmake();
+        game.unmake();
+        game.unmake();
+        let other = game.valid_actions()[1];
+        game.play(other).unwrap();
+        ai.think(&game, budget, &mut rng).unwrap();
+        let (history, tree) = ai.tree.as_ref().unwrap();
+        assert_eq!(history, &[other]);
+        assert!(*tree.state() == game.mcts_state());
+        assert!(tree.num_iterations() <= 300);
+    }
+
+    #[test]
+    fn reused_trees_agree_with_the_solver_more_often() {
+        use rand::Rng;
+        // Small enough to solve, and to get wrong with so little thought from scratch:
+        let budget = Budget::Iterations(10);
+        let mut rng = AiRng::seed_from_u64(0);
+        let (mut positions, mut reused_found, mut fresh_found) = (0, 0, 0);
+        while positions < 30 {
+            let mut state = empty_state(5, 5);
+            while state.board.num_free_cells() > 10 && !state.is_game_over() {
+                let actions = state.available_actions();
+                state.take_action(&actions[rng.gen_range(0, actions.len())]);
+            }
+            // Think about the previous move, then follow the line the tree expects, as `Ai` does:
+            let mut reused = Mcts::new(state.clone());
+            search(&mut reused, Budget::Iterations(500), 0, &mut rng);
+            for _ in 0..2 {
+                if let Some(&action) = reused.best_action() {
+                    reused.advance(&action);
+                }
+            }
+            let state = reused.state().clone();
+            if state.is_game_over() {
+                continue;
+            }
+            let value = |state: &GameState| crate::solver::solve(state, usize::MAX).unwrap().value;
+            let best_value = value(&state);
+            let is_best = |action: &Action| {
+                let mut child = state.clone();
+                child.take_action(action);
+                -value(&child) == best_value
+            };
+            if state.available_actions().iter().all(is_best) {
+                continue;
+            }
+            positions += 1;
+            let mut fresh = Mcts::new(state.clone());
+            search(&mut fresh, budget, 0, &mut rng);
+            search(&mut reused, budget, 0, &mut rng);
+            reused_found += is_best(reused.best_action().unwrap()) as usize;
+            fresh_found += is_best(fresh.best_action().unwrap()) as usize;
+        }
+        assert!(
+            reused_found >= fresh_found + 5,
+            "{} vs {} of {}",
+            reused_found,
+            fresh_found,
+            positions
+        );
+    }
+
+    #[test]
+    fn small_positions_are_solved() {
+        // The only winning move, see the solver tests:
+        let action = think(
+            empty_state(3, 1),
+            Budget::Iterations(100),
+            &mut AiRng::seed_from_u64(0),
+        );
+        assert_eq!(action, Some(Action::Move(Coord::new(1, 0))));
+    }
+}
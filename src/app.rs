@@ -1,48 +1,165 @@
 use std::collections::VecDeque;
+use std::convert::TryFrom;
 
 use serde::{Deserialize, Serialize};
 
 use egui::{
-    color::{srgba, Srgba},
-    label,
+    color::{srgba, Hsva, Srgba},
+    combo_box_with_label, label,
     math::*,
-    paint::TextStyle,
+    paint::{PaintCmd, TextStyle},
     widgets::*,
     Align, Painter, Ui,
 };
 
-use crate::hobogo::{Board, Coord, Player};
-
-#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+use crate::ai::{Ai, AiRng, Budget};
+use crate::game::Game;
+use crate::hobogo::{column_name, row_name, Board, Coord, Influence, Player, MAX_PLAYERS};
+use crate::map::{bundled_map, Map, BUNDLED_MAPS};
+use crate::mcts::Action;
+use crate::rules::RuleSet;
+use crate::setup::{Setup, MAX_HANDICAP};
+use crate::teams::{Team, Teams};
+use crate::topology::{Neighborhood, Topology};
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
 pub struct Settings {
     board_size: usize,
+    /// One of the bundled maps, or `None` for a square of `board_size`.
+    map: Option<String>,
     num_humans: usize,
     num_bots: usize,
     humans_first: bool,
+    rules: RuleSet,
+    topology: Topology,
+    /// Number of layers of the board.
+    layers: usize,
+    /// 0 for everyone for themselves.
+    num_teams: usize,
+    /// The team of each player, when playing in teams.
+    team_of: Vec<Team>,
+    /// Number of marks the `handicap_player` gets before the game starts.
+    handicap: usize,
+    handicap_player: Player,
+    /// A custom starting position, as a [`Setup`]. Empty for none.
+    setup: String,
+    /// How long each bot thinks, in turn order.
+    bots: Vec<Budget>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
             board_size: 9,
+            map: None,
             num_humans: 1,
             num_bots: 1,
             humans_first: true,
+            rules: RuleSet::default(),
+            topology: Topology::SQUARE,
+            layers: 1,
+            num_teams: 0,
+            team_of: vec![],
+            handicap: 0,
+            handicap_player: 0,
+            setup: String::new(),
+            bots: vec![Budget::default()],
         }
     }
 }
 
 impl Settings {
     fn num_players(&self) -> usize {
-        (self.num_humans + self.num_bots) as usize
+        self.num_humans + self.num_bots
+    }
+
+    /// How long the given bot thinks.
+    fn budget(&self, player: Player) -> Budget {
+        let bot = (player as usize).saturating_sub(self.num_humans);
+        self.bots.get(bot).copied().unwrap_or_default()
+    }
+
+    /// The picked teams, or the players dealt out to the teams in turn if those don't work out.
+    fn teams(&self) -> Teams {
+        if self.num_teams == 0 {
+            Teams::solo(self.num_players())
+        } else {
+            self.picked_teams()
+                .unwrap_or_else(|_| Teams::round_robin(self.num_players(), self.num_teams))
+        }
+    }
+
+    /// The team picked for each player, if every team has someone on it.
+    fn picked_teams(&self) -> Result<Teams, String> {
+        if self.team_of.len() != self.num_players() {
+            return Err("Not every player has a team".to_owned());
+        }
+        for team in 0..self.num_teams {
+            if !self.team_of.contains(&(team as Team)) {
+                return Err(format!("Team {} has no players", team_label(team as Team)));
+            }
+        }
+        Teams::try_from(self.team_of.clone()).map_err(|err| format!("{}", err))
+    }
+
+    /// Give every player one of the `num_teams` teams, dealing out newcomers in turn.
+    fn deal_teams(&mut self) {
+        let num_teams = self.num_teams.max(1);
+        self.team_of.truncate(self.num_players());
+        for player in 0..self.num_players() {
+            let dealt = (player % num_teams) as Team;
+            match self.team_of.get_mut(player) {
+                Some(team) if *team as usize >= num_teams => *team = dealt,
+                Some(_) => {}
+                None => self.team_of.push(dealt),
+            }
+        }
+    }
+
+    /// The board before anything is placed on it.
+    fn empty_board(&self) -> Board {
+        let map = self
+            .map
+            .as_deref()
+            .and_then(bundled_map)
+            .unwrap_or_else(|| Map::full(self.board_size as i32, self.board_size as i32));
+        Board::from_map(&map, self.num_players())
+            .with_topology(self.topology)
+            .with_layers(self.layers as i32)
+            .with_rules(self.rules)
+            .with_teams(self.teams())
+    }
+
+    /// The custom starting position, if it fits the board.
+    fn custom_setup(&self, board: &Board) -> Result<Setup, String> {
+        let setup: Setup = self.setup.parse().map_err(|err| format!("{}", err))?;
+        setup
+            .place_on(&mut board.clone())
+            .map_err(|(coord, err)| format!("{}: {}", coord, err))?;
+        Ok(setup)
+    }
+
+    /// The board with the custom starting position and the handicap marks.
+    fn starting_board(&self) -> Board {
+        let mut board = self.empty_board();
+        if let Ok(setup) = self.custom_setup(&board) {
+            setup.place_on(&mut board).unwrap();
+        }
+        let handicap = Setup::handicap(&board, self.handicap_player, self.handicap);
+        for &(coord, player) in handicap.marks() {
+            if board.at(coord).is_none() {
+                board.set(coord, player);
+            }
+        }
+        board
     }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct State {
     settings: Settings,
-    board: Board,
-    next_player: Player,
+    game: Game,
 }
 
 impl State {
@@ -52,15 +169,17 @@ impl State {
         } else {
             settings.num_humans as Player
         };
+        let board = settings.starting_board();
         State {
             settings,
-            board: Board::new(settings.board_size as i32, settings.board_size as i32),
-            next_player: first_player,
+            game: Game::new(board, first_player),
         }
     }
 
     pub fn is_valid(&self) -> bool {
-        self.settings.num_players() >= 2 && (self.next_player as usize) < self.num_players()
+        self.settings.num_players() >= 2
+            && (self.game.next_player() as usize) < self.num_players()
+            && self.game.num_players() == self.num_players()
     }
 
     pub fn from_local_storage() -> Option<Self> {
@@ -94,6 +213,10 @@ pub struct App {
 
     #[serde(skip_serializing)]
     ai_frame_delay: usize,
+
+    /// Keeps thinking between moves.
+    #[serde(skip)]
+    ai: Ai,
 }
 
 impl App {
@@ -102,6 +225,7 @@ impl App {
             state: State::new_or_restore(),
             undo_stack: Default::default(),
             ai_frame_delay: 0,
+            ai: Default::default(),
         }
     }
 
@@ -119,15 +243,27 @@ impl App {
 
         ui.columns(2, |cols| {
             if cols[0].add(Button::new("New Game")).clicked {
-                if !self.state.board.is_empty() {
+                if !self.state.game.history().is_empty() {
                     self.undo_stack.push_back(self.state.clone());
                 }
-                self.state = State::new(self.state.settings);
+                self.state = State::new(self.state.settings.clone());
                 self.state.save_to_local_storage();
             }
             if !self.undo_stack.is_empty() && cols[0].add(Button::new("Undo")).clicked {
                 self.state = self.undo_stack.pop_back().unwrap();
             }
+            let can_swap =
+                self.state.next_player_is_human() && self.state.game.swappable().is_some();
+            if can_swap
+                && cols[0]
+                    .add(Button::new("Swap"))
+                    .on_hover_text("Take over the first mark instead of making a move")
+                    .clicked
+            {
+                self.undo_stack.push_back(self.state.clone());
+                self.state.play(Action::Swap);
+                self.state.save_to_local_storage();
+            }
             self.state.show_score(&mut cols[1]);
         });
     }
@@ -139,22 +275,132 @@ impl App {
             .slider_width
             .min(ui.max_rect().width() / 6.0);
 
-        let mut settings = self.state.settings;
+        let mut settings = self.state.settings.clone();
         ui.columns(2, |cols| {
-            cols[0].add(Slider::usize(&mut settings.num_humans, 0..=4).text("Humans"));
-            cols[0].add(Slider::usize(&mut settings.num_bots, 0..=4).text("Bots"));
-            cols[1].add(Slider::usize(&mut settings.board_size, 5..=17).text("Size"));
+            cols[0].add(Slider::usize(&mut settings.num_humans, 0..=MAX_PLAYERS).text("Humans"));
+            let max_bots = MAX_PLAYERS - settings.num_humans;
+            settings.num_bots = settings.num_bots.min(max_bots);
+            cols[0].add(Slider::usize(&mut settings.num_bots, 0..=max_bots).text("Bots"));
+            let map_name = settings.map.clone().unwrap_or_else(|| "Square".to_owned());
+            combo_box_with_label(&mut cols[1], "Map", map_name, |ui| {
+                ui.radio_value(&mut settings.map, None, "Square");
+                for (name, _) in BUNDLED_MAPS {
+                    ui.radio_value(&mut settings.map, Some(name.to_string()), *name);
+                }
+            });
+            if settings.map.is_none() {
+                cols[1].add(Slider::usize(&mut settings.board_size, 5..=17).text("Size"));
+            }
+            cols[1]
+                .add(Slider::usize(&mut settings.layers, 1..=4).text("Layers"))
+                .on_hover_text("Stack boards on top of each other, shown side by side");
             cols[1]
                 .checkbox(&mut settings.humans_first, "Humans go first")
                 .on_hover_text("Going first is a big advantage");
         });
 
+        ui.horizontal(|ui| {
+            let neighborhood = &mut settings.topology.neighborhood;
+            ui.radio_value(neighborhood, Neighborhood::Moore, "Square")
+                .on_hover_text("Eight neighbors");
+            ui.radio_value(neighborhood, Neighborhood::VonNeumann, "Cross")
+                .on_hover_text("Four neighbors");
+            ui.radio_value(neighborhood, Neighborhood::Hex, "Hex")
+                .on_hover_text("Six neighbors");
+            ui.checkbox(&mut settings.topology.wrap, "Torus")
+                .on_hover_text("Opposite edges of the board are neighbors");
+        });
+
+        if settings.num_players() >= 4 {
+            ui.horizontal(|ui| {
+                let mut team_play = settings.num_teams > 0;
+                ui.checkbox(&mut team_play, "Teams")
+                    .on_hover_text("Teammates take turns and share their points");
+                if team_play {
+                    settings.num_teams = settings.num_teams.max(2);
+                    let max_teams = settings.num_players() / 2;
+                    ui.add(Slider::usize(&mut settings.num_teams, 2..=max_teams).text("teams"));
+                } else {
+                    settings.num_teams = 0;
+                }
+            });
+            if settings.num_teams > 0 {
+                self.show_team_settings(ui, &mut settings);
+            }
+        }
+
+        ui.collapsing("Rules", |ui| {
+            let rules = &mut settings.rules;
+            ui.add(Slider::u8(&mut rules.claim_margin, 1..=4).text("Claim margin"))
+                .on_hover_text("How many more neighbors than anyone else you need to claim a cell");
+            ui.checkbox(&mut rules.ties_allow_placement, "Ties allow placement");
+            ui.checkbox(&mut rules.count_claimed, "Claimed cells count as points");
+            ui.checkbox(&mut rules.end_when_settled, "End when settled")
+                .on_hover_text(
+                    "End the game when nothing can change who gets which cell \
+                     (only if claimed cells count)",
+                );
+            ui.checkbox(&mut rules.end_when_all_ruled, "End when all is ruled")
+                .on_hover_text("Only if claimed cells count");
+            ui.checkbox(&mut rules.end_on_unbeatable_lead, "End on unbeatable lead");
+            ui.checkbox(&mut rules.swap, "Swap rule")
+                .on_hover_text("Instead of the second move, you may take over the first mark");
+        });
+
+        ui.collapsing("Handicap", |ui| {
+            ui.add(Slider::usize(&mut settings.handicap, 0..=MAX_HANDICAP).text("Marks"))
+                .on_hover_text("Marks the weaker side gets before the game starts");
+            if settings.handicap > 0 {
+                let name = self.state.player_name(settings.handicap_player);
+                combo_box_with_label(ui, "For", name, |ui| {
+                    for player in 0..self.state.num_players() {
+                        let player = player as Player;
+                        let name = self.state.player_name(player);
+                        ui.radio_value(&mut settings.handicap_player, player, name);
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.label("Start position");
+                ui.add(TextEdit::new(&mut settings.setup))
+                    .on_hover_text("Marks to place before the game starts, e.g. C3=1,G7=1");
+            });
+            if let Err(err) = settings.custom_setup(&settings.empty_board()) {
+                ui.add(label!("{}", err).text_color(srgba(255, 64, 64, 255)));
+            }
+            if ui
+                .add(Button::new("Start from this position"))
+                .on_hover_text("Start new games with the marks that are on the board now")
+                .clicked
+            {
+                let setup = Setup::from_board(self.state.board());
+                settings.setup = if setup.is_empty() {
+                    String::new()
+                } else {
+                    setup.to_string()
+                };
+                settings.handicap = 0;
+            }
+        });
+
+        self.show_bot_settings(ui, &mut settings);
+
         while settings.num_players() < 2 {
             settings.num_humans += 1;
         }
+        if settings.num_teams > settings.num_players() / 2 {
+            // Too few players left for team play:
+            settings.num_teams = 0;
+        }
+        if settings.handicap_player as usize >= settings.num_players() {
+            settings.handicap_player = 0;
+        }
+
+        // Bots can be tuned without starting over:
+        self.state.settings.bots = settings.bots.clone();
 
         if settings != self.state.settings {
-            if !self.state.board.is_empty() {
+            if !self.state.game.history().is_empty() {
                 self.undo_stack.push_back(self.state.clone());
             }
             self.state = State::new(settings);
@@ -162,12 +408,88 @@ impl App {
         }
     }
 
+    /// Pick the team of each player.
+    fn show_team_settings(&self, ui: &mut Ui, settings: &mut Settings) {
+        settings.deal_teams();
+        let num_teams = settings.num_teams;
+        for player in 0..settings.num_players() {
+            let mut seat = format!("Player {}", player + 1);
+            if player >= settings.num_humans {
+                seat += " (bot)";
+            }
+            let team = &mut settings.team_of[player];
+            combo_box_with_label(ui, seat, format!("Team {}", team_label(*team)), |ui| {
+                for other in 0..num_teams {
+                    let other = other as Team;
+                    ui.radio_value(team, other, format!("Team {}", team_label(other)));
+                }
+            });
+        }
+        if let Err(err) = settings.picked_teams() {
+            ui.add(label!("{}", err).text_color(srgba(255, 64, 64, 255)));
+        }
+    }
+
+    fn show_bot_settings(&self, ui: &mut Ui, settings: &mut Settings) {
+        settings.bots.resize(settings.num_bots, Budget::default());
+        if settings.num_bots == 0 {
+            return;
+        }
+        ui.collapsing("Bots", |ui| {
+            for (bot, budget) in settings.bots.iter_mut().enumerate() {
+                let player = (settings.num_humans + bot) as Player;
+                if (player as usize) < self.state.num_players() {
+                    ui.label(self.state.player_name(player));
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .radio(matches!(budget, Budget::Iterations(_)), "Iterations")
+                        .on_hover_text("Think the same amount every time")
+                        .clicked
+                    {
+                        *budget = Budget::Iterations(10_000);
+                    }
+                    if ui
+                        .radio(matches!(budget, Budget::Seconds(_)), "Seconds")
+                        .on_hover_text("Think for the same time every move")
+                        .clicked
+                    {
+                        *budget = Budget::Seconds(1.0);
+                    }
+                    if ui
+                        .radio(matches!(budget, Budget::Scaled(_)), "Scaled")
+                        .on_hover_text("Think faster as the board fills up")
+                        .clicked
+                    {
+                        *budget = Budget::Scaled(2.0);
+                    }
+                });
+                match budget {
+                    Budget::Iterations(iterations) => {
+                        ui.add(
+                            Slider::usize(iterations, 100..=100_000)
+                                .logarithmic(true)
+                                .text("iterations"),
+                        );
+                    }
+                    Budget::Seconds(seconds) | Budget::Scaled(seconds) => {
+                        ui.add(
+                            Slider::f64(seconds, 0.1..=10.0)
+                                .logarithmic(true)
+                                .text("seconds"),
+                        );
+                    }
+                }
+            }
+        });
+    }
+
     fn show_board_and_interact(&mut self, ui: &mut Ui) {
         // Add spacing before the board:
         ui.advance_cursor(8.0);
 
-        let size = ui.max_rect().width() - 32.0; // Leave space for row numbers
-        let rect = ui.allocate_space(vec2(size, size));
+        let width = ui.max_rect().width() - 32.0; // Leave space for row numbers
+        let rect = ui.allocate_space(board_size(self.state.board(), width));
         let board_id = ui.make_position_id();
         let board_interact = ui.interact(rect, board_id, egui::Sense::click());
 
@@ -176,28 +498,31 @@ impl App {
 
         let state = &mut self.state;
 
-        if !state.board.is_game_over(state.num_players()) {
+        let hovered = if board_interact.hovered {
+            let mouse_pos = ui.input().mouse.pos;
+            mouse_pos.and_then(|mouse_pos| hovered_coord(state.board(), &rect, mouse_pos))
+        } else {
+            None
+        };
+
+        if !state.game.is_game_over() {
             if state.next_player_is_human() {
-                if board_interact.hovered {
-                    if let Some(mouse_pos) = ui.input().mouse.pos {
-                        if let Some(hovered_coord) = hovered_coord(&state.board, &rect, mouse_pos) {
-                            if state.board.is_valid_move(
-                                hovered_coord,
-                                state.next_player,
-                                state.num_players(),
-                            ) {
-                                if board_interact.clicked {
-                                    self.undo_stack.push_back(state.clone());
-                                    state.board[hovered_coord] = Some(state.next_player);
-                                    state.next_player =
-                                        (state.next_player + 1) % (state.num_players() as u8);
-                                    state.save_to_local_storage();
-                                } else {
-                                    let mut preview = state.clone();
-                                    preview.board[hovered_coord] = Some(state.next_player);
-                                    return preview.show_board(rect, ui.painter());
-                                }
-                            }
+                if state.game.valid_actions() == [Action::Pass] {
+                    // Nowhere to go:
+                    self.undo_stack.push_back(state.clone());
+                    state.play(Action::Pass);
+                    state.save_to_local_storage();
+                } else if let Some(hovered_coord) = hovered {
+                    let action = Action::Move(hovered_coord);
+                    if state.game.check(action).is_ok() {
+                        if board_interact.clicked {
+                            self.undo_stack.push_back(state.clone());
+                            state.play(action);
+                            state.save_to_local_storage();
+                        } else {
+                            let mut preview = state.clone();
+                            preview.play(action);
+                            return preview.show_board(rect, ui.painter(), hovered);
                         }
                     }
                 }
@@ -213,29 +538,55 @@ impl App {
                     } else {
                         self.ai_frame_delay = 0;
 
-                        if let Some(coord) =
-                            state.board.ai_move(state.next_player, state.num_players())
-                        {
-                            state.board[coord] = Some(state.next_player);
-                        }
-                        state.next_player = (state.next_player + 1) % (state.num_players() as u8);
+                        let budget = state.settings.budget(state.next_player());
+                        use rand::SeedableRng;
+                        let mut rng = AiRng::from_entropy();
+                        let action = self
+                            .ai
+                            .think(&state.game, budget, &mut rng)
+                            .unwrap_or(Action::Pass);
+                        state.play(action);
                     }
                 }
                 ui.ctx().request_repaint();
             }
         }
 
-        state.show_board(rect, ui.painter());
+        state.show_board(rect, ui.painter(), hovered);
     }
 }
 
 impl State {
+    fn board(&self) -> &Board {
+        self.game.board()
+    }
+
+    fn next_player(&self) -> Player {
+        self.game.next_player()
+    }
+
+    fn play(&mut self, action: Action) {
+        if let Err(err) = self.game.play(action) {
+            egui_web::console_log(format!("Failed to play {}: {}", action, err));
+        }
+    }
+
     pub fn show_whos_next(&mut self, ui: &mut Ui) {
-        if self.board.is_game_over(self.num_players()) {
-            ui.add(label!("Game over!"));
+        if let Some(result) = self.game.result() {
+            if let Some(winner) = result.winner() {
+                let team_name = self.team_name(winner);
+                ui.add(label!("Game over! {} wins", team_name).text_color(team_color(winner)));
+            } else {
+                let names: Vec<String> = result
+                    .winners()
+                    .iter()
+                    .map(|&team| self.team_name(team))
+                    .collect();
+                ui.add(label!("Game over! Tie between {}", names.join(" and ")));
+            }
         } else {
-            let player_color = player_color(self.next_player);
-            let player_name = self.player_name(self.next_player);
+            let player_color = self.player_color(self.next_player());
+            let player_name = self.player_name(self.next_player());
             if self.next_player_is_human() {
                 ui.add(label!("{} to play", player_name).text_color(player_color));
             } else {
@@ -246,17 +597,17 @@ impl State {
 
     pub fn show_score(&mut self, ui: &mut Ui) {
         ui.columns(2, |cols| {
-            let score = self.board.points();
-            for pi in 0..self.num_players() {
-                let player_color = player_color(pi as Player);
-                let player_name = self.player_name(pi as Player);
-                cols[0].add(label!("{}", player_name).text_color(player_color));
-                cols[1].add(label!("{}", score[pi]).text_color(player_color));
+            let score = self.board().points();
+            for (team, score) in score.iter().enumerate() {
+                let team_color = team_color(team as Team);
+                let team_name = self.team_name(team as Team);
+                cols[0].add(label!("{}", team_name).text_color(team_color));
+                cols[1].add(label!("{}", score).text_color(team_color));
             }
         });
 
         /*
-        let score = self.board.points();
+        let score = self.board().points();
         let mut cursor = ui.cursor();
         for pi in 0..self.num_players() {
             let player_color = player_color(pi as Player);
@@ -289,17 +640,25 @@ impl State {
     }
 
     fn next_player_is_human(&self) -> bool {
-        self.is_human(self.next_player) && !self.board.is_game_over(self.num_players())
+        self.is_human(self.next_player()) && !self.game.is_game_over()
+    }
+
+    fn teams(&self) -> &Teams {
+        self.board().teams()
     }
 
+    /// Teammates are named after their team: "Yellow 1", "Yellow 2", …
     fn player_name(&self, player: Player) -> String {
-        let mut name = match player {
-            0 => "Yellow".to_string(),
-            1 => "Pink".to_string(),
-            2 => "Green".to_string(),
-            3 => "Purple".to_string(),
-            i => i.to_string(),
+        let teams = self.teams();
+        let team = teams.team_of(player);
+        let mut name = match PALETTE.get(team as usize) {
+            Some((name, _)) => name.to_string(),
+            None => format!("Player {}", team as usize + 1),
         };
+        if !teams.is_solo() {
+            let number = teams.members(team).position(|p| p == player).unwrap() + 1;
+            name += &format!(" {}", number);
+        }
 
         if !self.is_human(player) {
             name += " (bot)";
@@ -308,10 +667,25 @@ impl State {
         name
     }
 
-    fn show_board(&self, rect: Rect, painter: &Painter) {
-        let board = &self.board;
-        let spacing = rect.width() / (board.width as f32);
-        let volatile = board.volatile_cells(self.num_players());
+    /// The name of the player, unless they play in teams.
+    fn team_name(&self, team: Team) -> String {
+        let teams = self.teams();
+        if teams.is_solo() {
+            self.player_name(teams.leader(team))
+        } else {
+            format!("Team {}", team_label(team))
+        }
+    }
+
+    fn player_color(&self, player: Player) -> Srgba {
+        team_color(self.teams().team_of(player))
+    }
+
+    /// `hovered` is the cell under the mouse, if any.
+    fn show_board(&self, rect: Rect, painter: &Painter, hovered: Option<Coord>) {
+        let board = self.board();
+        let spacing = cell_spacing(board, rect.width());
+        let volatile = board.volatile_cells();
 
         let cell_side = spacing * 0.84;
         let corner_radius = (cell_side * 0.25).round();
@@ -321,43 +695,79 @@ impl State {
             painter.rect_stroke(
                 rect.expand(4.0),
                 corner_radius * 2.0f32.sqrt(),
-                (2.0, player_color(self.next_player)),
+                (2.0, self.player_color(self.next_player())),
             );
         }
 
         for c in board.coords() {
-            let center = rect.min + spacing * vec2(c.x as f32 + 0.5, c.y as f32 + 0.5);
+            let center = cell_center(board, &rect, c);
 
             let is_volatile = volatile[board.index(c).unwrap()];
             let fill = self.cell_color(c, is_volatile);
 
-            if let Some(_player) = board[c] {
-                let rect = Rect::from_center_size(center, vec2(cell_side, cell_side));
-                painter.rect_filled(rect, corner_radius, fill);
+            if board.at(c).is_some() {
+                if board.topology().is_hex() {
+                    painter.add(PaintCmd::Path {
+                        points: hexagon(center, cell_side),
+                        closed: true,
+                        fill,
+                        stroke: Default::default(),
+                    });
+                } else {
+                    let rect = Rect::from_center_size(center, vec2(cell_side, cell_side));
+                    painter.rect_filled(rect, corner_radius, fill);
+                }
             } else {
                 painter.circle_filled(center, 0.2 * spacing, fill);
             }
+
+            // Show where the hovered cell is on the other layers:
+            let above_or_below_hovered =
+                hovered.is_some_and(|h| h.x == c.x && h.y == c.y && h.z != c.z);
+            if above_or_below_hovered {
+                painter.circle_stroke(center, 0.45 * spacing, (2.0, srgba(200, 200, 210, 255)));
+            }
         }
 
         let text_color = srgba(100, 100, 100, 255);
 
-        // Name chess column names:
-        for x in 0..board.width {
-            painter.text(
-                rect.min + vec2((x as f32 + 0.5) * spacing, rect.height() + 12.0),
-                (Align::Center, Align::Min),
-                &column_name(x),
-                TextStyle::Body,
-                text_color,
-            );
+        // Name chess column names (under every layer):
+        for z in 0..board.depth {
+            for x in 0..board.width {
+                let bottom_cell = cell_center(
+                    board,
+                    &rect,
+                    Coord {
+                        x,
+                        y: board.height - 1,
+                        z,
+                    },
+                );
+                painter.text(
+                    pos2(bottom_cell.x, rect.bottom() + 12.0),
+                    (Align::Center, Align::Min),
+                    column_name(x),
+                    TextStyle::Body,
+                    text_color,
+                );
+            }
         }
 
-        // Name chess row names:
+        // Name chess row names (right of the last layer):
         for y in 0..board.height {
+            let last_cell = cell_center(
+                board,
+                &rect,
+                Coord {
+                    x: board.width - 1,
+                    y,
+                    z: board.depth - 1,
+                },
+            );
             painter.text(
-                rect.min + vec2(rect.width() + 12.0, (y as f32 + 0.5) * spacing),
+                pos2(last_cell.x + 0.5 * spacing + 12.0, last_cell.y),
                 (Align::Min, Align::Center),
-                &row_name(y),
+                row_name(y),
                 TextStyle::Body,
                 text_color,
             );
@@ -365,67 +775,215 @@ impl State {
     }
 
     fn cell_color(&self, c: Coord, is_volatile: bool) -> Srgba {
-        let influence = self.board.influence(c);
-        if let Some(claimer) = influence.player() {
-            let color = player_color(claimer);
+        let influence = self.board().influence(c);
+        if let Some(claimer) = influence.team(self.teams()) {
+            let color = team_color(claimer);
             if is_volatile || influence.is_occupied() {
                 color
             } else {
                 srgba(color.r() / 2, color.g() / 2, color.b() / 2, color.a()) // Darker
             }
-        } else if self.next_player_is_human()
-            && !self
-                .board
-                .is_valid_move(c, self.next_player, self.num_players())
-        {
-            // The currant human can't move here
-            srgba(90, 90, 100, 255)
         } else {
-            // Free (at least for some)
-            srgba(150, 150, 160, 255)
+            let free_color = if self.next_player_is_human()
+                && !self.board().is_valid_move(c, self.next_player())
+            {
+                // The currant human can't move here
+                srgba(90, 90, 100, 255)
+            } else {
+                // Free (at least for some)
+                srgba(150, 150, 160, 255)
+            };
+
+            match influence {
+                Influence::Tied(teams, count)
+                    if count > 0 && teams.len() < self.teams().num_teams() =>
+                {
+                    // Contested by some, but not all, teams:
+                    let colors: Vec<Srgba> = teams.iter().map(team_color).collect();
+                    lerp_color(free_color, average_color(&colors), 0.4)
+                }
+                _ => free_color,
+            }
+        }
+    }
+}
+
+fn lerp_color(a: Srgba, b: Srgba, t: f32) -> Srgba {
+    let lerp = |a: u8, b: u8| (a as f32 + t * (b as f32 - a as f32)).round() as u8;
+    srgba(
+        lerp(a.r(), b.r()),
+        lerp(a.g(), b.g()),
+        lerp(a.b(), b.b()),
+        lerp(a.a(), b.a()),
+    )
+}
+
+fn average_color(colors: &[Srgba]) -> Srgba {
+    let n = colors.len() as u32;
+    let average = |channel: fn(&Srgba) -> u8| {
+        (colors.iter().map(|c| channel(c) as u32).sum::<u32>() / n) as u8
+    };
+    srgba(
+        average(Srgba::r),
+        average(Srgba::g),
+        average(Srgba::b),
+        average(Srgba::a),
+    )
+}
+
+/// Names and colors of the first teams (players, unless they play in teams).
+/// Any further teams get generated colors.
+const PALETTE: &[(&str, [u8; 3])] = &[
+    ("Yellow", [239, 169, 0]),
+    ("Pink", [242, 73, 117]),
+    ("Green", [31, 187, 171]),
+    ("Purple", [121, 68, 219]),
+    ("Blue", [52, 132, 235]),
+    ("Orange", [245, 110, 35]),
+    ("Lime", [140, 200, 40]),
+    ("Brown", [160, 100, 60]),
+];
+
+/// The color of the team, or its number if we have run out of color names.
+fn team_label(team: Team) -> String {
+    match PALETTE.get(team as usize) {
+        Some((name, _)) => name.to_string(),
+        None => format!("{}", team as usize + 1),
+    }
+}
+
+fn team_color(team: Team) -> Srgba {
+    match PALETTE.get(team as usize) {
+        Some((_, [r, g, b])) => srgba(*r, *g, *b, 255),
+        None => {
+            // Step around the color wheel by the golden ratio, so that hues never repeat:
+            let hue = (team as f32 * 0.618_034).fract();
+            Hsva::new(hue, 0.7, 0.9, 1.0).into()
         }
     }
 }
 
-fn player_color(player: Player) -> Srgba {
-    match player {
-        // 0 => srgba(85, 119, 255, 255),
-        // 1 => srgba(205, 0, 0, 255),
-        // 2 => srgba(0, 255, 0, 255),
-        // _ => srgba(221, 221, 0, 255),
-        0 => srgba(239, 169, 0, 255),
-        1 => srgba(242, 73, 117, 255),
-        2 => srgba(31, 187, 171, 255),
-        _ => srgba(121, 68, 219, 255),
+/// Room between the layers of a layered board, which are shown side by side.
+const LAYER_GAP: f32 = 24.0;
+
+/// How wide each layer is, when the board is `width` points wide.
+fn layer_width(board: &Board, width: f32) -> f32 {
+    (width - LAYER_GAP * (board.depth - 1) as f32) / board.depth as f32
+}
+
+/// Distance between the centers of two cells next to each other in a row,
+/// when the board is `width` points wide.
+fn cell_spacing(board: &Board, width: f32) -> f32 {
+    let width = layer_width(board, width);
+    if board.topology().is_hex() {
+        // Each row is shifted half a cell to the right of the one above:
+        width / (board.width as f32 + 0.5 * (board.height - 1) as f32)
+    } else {
+        width / (board.width as f32)
+    }
+}
+
+/// How much room the board needs when it is `width` points wide.
+fn board_size(board: &Board, width: f32) -> Vec2 {
+    let spacing = cell_spacing(board, width);
+    if board.topology().is_hex() {
+        let row_height = spacing * 3.0f32.sqrt() / 2.0;
+        vec2(
+            width,
+            row_height * (board.height - 1) as f32 + hex_radius(spacing) * 2.0,
+        )
+    } else {
+        vec2(width, spacing * board.height as f32)
     }
 }
 
-/// Chess coordinate name
-fn column_name(x: i32) -> String {
-    ((65 + (x as u8)) as char).to_string()
+fn cell_center(board: &Board, rect: &Rect, c: Coord) -> Pos2 {
+    let spacing = cell_spacing(board, rect.width());
+    let layer_min = rect.min
+        + vec2(
+            c.z as f32 * (layer_width(board, rect.width()) + LAYER_GAP),
+            0.0,
+        );
+    if board.topology().is_hex() {
+        let row_height = spacing * 3.0f32.sqrt() / 2.0;
+        layer_min
+            + vec2(
+                spacing * (c.x as f32 + 0.5 * c.y as f32 + 0.5),
+                row_height * c.y as f32 + hex_radius(spacing),
+            )
+    } else {
+        layer_min + spacing * vec2(c.x as f32 + 0.5, c.y as f32 + 0.5)
+    }
+}
+
+/// Distance from the center to the corners of a hexagon that is `width` wide.
+fn hex_radius(width: f32) -> f32 {
+    width / 3.0f32.sqrt()
 }
 
-/// Chess coordinate name
-fn row_name(y: i32) -> String {
-    y.to_string()
+/// The corners of a pointy-topped hexagon.
+fn hexagon(center: Pos2, width: f32) -> Vec<Pos2> {
+    let radius = hex_radius(width);
+    (0..6)
+        .map(|i| {
+            let angle = (60.0 * i as f32 - 90.0).to_radians();
+            center + radius * vec2(angle.cos(), angle.sin())
+        })
+        .collect()
 }
 
 fn hovered_coord(board: &Board, rect: &Rect, mouse_pos: Pos2) -> Option<Coord> {
-    let spacing = rect.width() / (board.width as f32);
-    for c in board.coords() {
-        let x = c.x as f32 * spacing + rect.left();
-        let y = c.y as f32 * spacing + rect.top();
-        let left = x;
-        let top = y;
-        let right = x + spacing;
-        let bottom = y + spacing;
-        let is_hovering = left <= mouse_pos.x
-            && mouse_pos.x <= right
-            && top <= mouse_pos.y
-            && mouse_pos.y <= bottom;
-        if is_hovering {
-            return Some(c);
-        }
+    let spacing = cell_spacing(board, rect.width());
+    if board.topology().is_hex() {
+        // Hexagons are exactly the areas closest to their centers:
+        return board
+            .coords()
+            .map(|c| (c, (cell_center(board, rect, c) - mouse_pos).length()))
+            .filter(|&(_, distance)| distance <= hex_radius(spacing))
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(c, _)| c);
+    }
+    board.coords().find(|&c| {
+        let delta = mouse_pos - cell_center(board, rect, c);
+        delta.x.abs() <= 0.5 * spacing && delta.y.abs() <= 0.5 * spacing
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picked_teams() {
+        let mut settings = Settings {
+            num_humans: 2,
+            num_bots: 2,
+            num_teams: 2,
+            ..Settings::default()
+        };
+        settings.deal_teams();
+        assert_eq!(settings.team_of, vec![0, 1, 0, 1]);
+
+        // The humans against the bots:
+        settings.team_of = vec![0, 0, 1, 1];
+        assert_eq!(settings.teams(), Teams::try_from(vec![0, 0, 1, 1]).unwrap());
+        assert_eq!(settings.empty_board().teams(), &settings.teams());
+
+        // Nobody left for the second team, so deal them out in turn:
+        settings.team_of = vec![0, 0, 0, 0];
+        assert_eq!(
+            settings.picked_teams(),
+            Err("Team Pink has no players".to_owned())
+        );
+        assert_eq!(settings.teams(), Teams::round_robin(4, 2));
+
+        // A fifth player joins the first team, and a sixth the second:
+        settings.team_of = vec![0, 0, 1, 1];
+        settings.num_bots = 4;
+        settings.deal_teams();
+        assert_eq!(settings.team_of, vec![0, 0, 1, 1, 0, 1]);
+
+        settings.num_teams = 0;
+        assert!(settings.teams().is_solo());
     }
-    None
 }
@@ -0,0 +1,404 @@
+//! Fixed-size bitsets for doing things to many board cells at once.
+
+use std::ops::{BitAndAssign, BitOrAssign, BitXorAssign};
+
+/// A set of bit indices in `0..len`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Bits {
+    len: usize,
+    words: Vec<u64>,
+}
+
+impl Bits {
+    /// All bits cleared.
+    pub fn new(len: usize) -> Self {
+        Bits {
+            len,
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    /// All bits set.
+    pub fn full(len: usize) -> Self {
+        let mut bits = Bits {
+            len,
+            words: vec![!0; len.div_ceil(64)],
+        };
+        bits.clear_tail();
+        bits
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        debug_assert!(i < self.len);
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn insert(&mut self, i: usize) {
+        debug_assert!(i < self.len);
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    pub fn remove(&mut self, i: usize) {
+        debug_assert!(i < self.len);
+        self.words[i / 64] &= !(1 << (i % 64));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(wi, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    None
+                } else {
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    Some(wi * 64 + bit)
+                }
+            })
+        })
+    }
+
+    /// The index of the n:th set bit (zero-based), if any.
+    pub fn nth_one(&self, mut n: usize) -> Option<usize> {
+        for (wi, &word) in self.words.iter().enumerate() {
+            let ones = word.count_ones() as usize;
+            if n < ones {
+                let mut word = word;
+                for _ in 0..n {
+                    word &= word - 1;
+                }
+                return Some(wi * 64 + word.trailing_zeros() as usize);
+            }
+            n -= ones;
+        }
+        None
+    }
+
+    /// Remove all bits that are set in `other`.
+    pub fn and_not_assign(&mut self, other: &Bits) {
+        debug_assert_eq!(self.len, other.len);
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= !b;
+        }
+    }
+
+    /// Move every bit `offset` steps towards higher indices (or lower, if negative).
+    /// Bits that end up outside of `0..len` are dropped.
+    pub fn shifted(&self, offset: isize) -> Bits {
+        let mut result = Bits::new(self.len);
+        let n = self.words.len();
+        let word_shift = offset.unsigned_abs() / 64;
+        let bit_shift = (offset.unsigned_abs() % 64) as u32;
+
+        if word_shift >= n {
+            return result;
+        }
+
+        if offset >= 0 {
+            for i in word_shift..n {
+                let mut word = self.words[i - word_shift] << bit_shift;
+                if bit_shift > 0 && i > word_shift {
+                    word |= self.words[i - word_shift - 1] >> (64 - bit_shift);
+                }
+                result.words[i] = word;
+            }
+        } else {
+            for i in 0..(n - word_shift) {
+                let mut word = self.words[i + word_shift] >> bit_shift;
+                if bit_shift > 0 && i + word_shift + 1 < n {
+                    word |= self.words[i + word_shift + 1] << (64 - bit_shift);
+                }
+                result.words[i] = word;
+            }
+        }
+
+        result.clear_tail();
+        result
+    }
+
+    fn clear_tail(&mut self) {
+        let used = self.len % 64;
+        if used != 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1 << used) - 1;
+            }
+        }
+    }
+}
+
+impl BitAndAssign<&Bits> for Bits {
+    fn bitand_assign(&mut self, other: &Bits) {
+        debug_assert_eq!(self.len, other.len);
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= b;
+        }
+    }
+}
+
+impl BitOrAssign<&Bits> for Bits {
+    fn bitor_assign(&mut self, other: &Bits) {
+        debug_assert_eq!(self.len, other.len);
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a |= b;
+        }
+    }
+}
+
+impl BitXorAssign<&Bits> for Bits {
+    fn bitxor_assign(&mut self, other: &Bits) {
+        debug_assert_eq!(self.len, other.len);
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a ^= b;
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// One small counter per bit index, stored bit-sliced so that
+/// all counters can be added to and compared in parallel.
+#[derive(Clone, Debug)]
+pub struct Counter {
+    len: usize,
+    /// `planes[i]` holds bit `i` of every counter.
+    planes: Vec<Bits>,
+}
+
+impl Counter {
+    pub fn new(len: usize) -> Self {
+        Counter {
+            len,
+            planes: vec![],
+        }
+    }
+
+    /// Increment the counters of all indices set in `bits`.
+    pub fn add(&mut self, bits: &Bits) {
+        let mut carry = bits.clone();
+        for plane in &mut self.planes {
+            if carry.is_empty() {
+                return;
+            }
+            let mut next_carry = plane.clone();
+            next_carry &= &carry;
+            *plane ^= &carry;
+            carry = next_carry;
+        }
+        if !carry.is_empty() {
+            self.planes.push(carry);
+        }
+    }
+
+    /// The counter value at one index.
+    pub fn get(&self, i: usize) -> u32 {
+        self.planes
+            .iter()
+            .enumerate()
+            .map(|(bit, plane)| (plane.get(i) as u32) << bit)
+            .sum()
+    }
+
+    /// The indices where the counter is not zero.
+    pub fn nonzero(&self) -> Bits {
+        let mut nonzero = Bits::new(self.len);
+        for plane in &self.planes {
+            nonzero |= plane;
+        }
+        nonzero
+    }
+
+    /// The indices where both counters are the same.
+    pub fn equal_to(&self, other: &Counter) -> Bits {
+        let mut equal = Bits::full(self.len);
+        equal.and_not_assign(&self.greater_than(other));
+        equal.and_not_assign(&other.greater_than(self));
+        equal
+    }
+
+    /// The indices where our counter is strictly larger than the other one.
+    pub fn greater_than(&self, other: &Counter) -> Bits {
+        debug_assert_eq!(self.len, other.len);
+        let zero = Bits::new(self.len);
+        let num_planes = self.planes.len().max(other.planes.len());
+
+        let mut greater = Bits::new(self.len);
+        let mut equal = Bits::full(self.len);
+
+        for i in (0..num_planes).rev() {
+            let a = self.planes.get(i).unwrap_or(&zero);
+            let b = other.planes.get(i).unwrap_or(&zero);
+
+            let mut a_not_b = a.clone();
+            a_not_b.and_not_assign(b);
+            a_not_b &= &equal;
+            greater |= &a_not_b;
+
+            let mut differ = a.clone();
+            differ ^= b;
+            equal.and_not_assign(&differ);
+        }
+
+        greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::ai::AiRng;
+
+    const LENS: [usize; 7] = [1, 5, 63, 64, 65, 128, 200];
+
+    fn random_bits(rng: &mut AiRng, len: usize) -> (Bits, Vec<bool>) {
+        let mut bits = Bits::new(len);
+        let mut naive = vec![false; len];
+        for (i, bit) in naive.iter_mut().enumerate() {
+            if rng.gen() {
+                bits.insert(i);
+                *bit = true;
+            }
+        }
+        (bits, naive)
+    }
+
+    fn ones(naive: &[bool]) -> Vec<usize> {
+        (0..naive.len()).filter(|&i| naive[i]).collect()
+    }
+
+    #[test]
+    fn full_has_every_bit_and_no_more() {
+        for &len in &LENS {
+            let full = Bits::full(len);
+            assert_eq!(full.count_ones(), len);
+            assert_eq!(
+                full.iter_ones().collect::<Vec<_>>(),
+                (0..len).collect::<Vec<_>>()
+            );
+            assert!(Bits::new(len).is_empty());
+        }
+    }
+
+    #[test]
+    fn iter_ones_and_nth_one() {
+        let mut rng = AiRng::seed_from_u64(0);
+        for &len in &LENS {
+            let (mut bits, mut naive) = random_bits(&mut rng, len);
+            let i = rng.gen_range(0, len);
+            bits.remove(i);
+            naive[i] = false;
+            let ones = ones(&naive);
+            assert_eq!(bits.iter_ones().collect::<Vec<_>>(), ones);
+            assert_eq!(bits.count_ones(), ones.len());
+            for (n, &one) in ones.iter().enumerate() {
+                assert_eq!(bits.nth_one(n), Some(one));
+            }
+            assert_eq!(bits.nth_one(ones.len()), None);
+        }
+    }
+
+    #[test]
+    fn shifted_drops_what_falls_off() {
+        let mut rng = AiRng::seed_from_u64(1);
+        for &len in &LENS {
+            let (bits, naive) = random_bits(&mut rng, len);
+            for offset in -(len as isize) - 70..len as isize + 70 {
+                let expected: Vec<usize> = ones(&naive)
+                    .into_iter()
+                    .map(|i| i as isize + offset)
+                    .filter(|&i| 0 <= i && i < len as isize)
+                    .map(|i| i as usize)
+                    .collect();
+                let shifted = bits.shifted(offset);
+                assert_eq!(
+                    shifted.iter_ones().collect::<Vec<_>>(),
+                    expected,
+                    "len {}, offset {}",
+                    len,
+                    offset
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn set_operations() {
+        let mut rng = AiRng::seed_from_u64(2);
+        for &len in &LENS {
+            let (a, a_naive) = random_bits(&mut rng, len);
+            let (b, b_naive) = random_bits(&mut rng, len);
+            let expected = |op: fn(bool, bool) -> bool| -> Vec<usize> {
+                (0..len).filter(|&i| op(a_naive[i], b_naive[i])).collect()
+            };
+            let mut and = a.clone();
+            and &= &b;
+            assert_eq!(and.iter_ones().collect::<Vec<_>>(), expected(|a, b| a && b));
+            let mut or = a.clone();
+            or |= &b;
+            assert_eq!(or.iter_ones().collect::<Vec<_>>(), expected(|a, b| a || b));
+            let mut xor = a.clone();
+            xor ^= &b;
+            assert_eq!(xor.iter_ones().collect::<Vec<_>>(), expected(|a, b| a != b));
+            let mut and_not = a.clone();
+            and_not.and_not_assign(&b);
+            assert_eq!(
+                and_not.iter_ones().collect::<Vec<_>>(),
+                expected(|a, b| a && !b)
+            );
+        }
+    }
+
+    #[test]
+    fn counters_count_and_compare() {
+        let mut rng = AiRng::seed_from_u64(3);
+        for &len in &LENS {
+            let mut counters = [Counter::new(len), Counter::new(len)];
+            let mut naive = [vec![0; len], vec![0; len]];
+            for _ in 0..20 {
+                let which = rng.gen_range(0, 2);
+                let (bits, added) = random_bits(&mut rng, len);
+                counters[which].add(&bits);
+                for (count, added) in naive[which].iter_mut().zip(added) {
+                    *count += added as u32;
+                }
+
+                let (a, b) = (&counters[0], &counters[1]);
+                let compare = |op: fn(u32, u32) -> bool| -> Vec<usize> {
+                    (0..len).filter(|&i| op(naive[0][i], naive[1][i])).collect()
+                };
+                for (counter, naive) in counters.iter().zip(&naive) {
+                    assert_eq!((0..len).map(|i| counter.get(i)).collect::<Vec<_>>(), *naive);
+                }
+                assert_eq!(
+                    a.nonzero().iter_ones().collect::<Vec<_>>(),
+                    compare(|a, _| a > 0)
+                );
+                assert_eq!(
+                    a.greater_than(b).iter_ones().collect::<Vec<_>>(),
+                    compare(|a, b| a > b)
+                );
+                assert_eq!(
+                    b.greater_than(a).iter_ones().collect::<Vec<_>>(),
+                    compare(|a, b| a < b)
+                );
+                assert_eq!(
+                    a.equal_to(b).iter_ones().collect::<Vec<_>>(),
+                    compare(|a, b| a == b)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{ai_action, ai_action_seeded, think, AiRng, Budget};
+use crate::hobogo::{Board, Coord, GameResult, HobogoError, Player};
+use crate::mcts::{Action, GameState};
+use crate::setup::Setup;
+
+/// A game in progress: the board, whose turn it is, and how we got here.
+///
+/// All moves go through [`Game::play`], which checks that they follow the rules.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(try_from = "GameData")]
+pub struct Game {
+    board: Board,
+    /// The marks that were on the board before the first move.
+    #[serde(default)]
+    setup: Setup,
+    first_player: Player,
+    next_player: Player,
+    /// Every action taken so far, in order.
+    history: Vec<Action>,
+}
+
+/// The serialized form of a [`Game`], before it has been checked.
+#[derive(Deserialize)]
+struct GameData {
+    board: Board,
+    #[serde(default)]
+    setup: Setup,
+    first_player: Player,
+    next_player: Player,
+    history: Vec<Action>,
+}
+
+/// Why a serialized [`Game`] could not be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidGameData(String);
+
+impl fmt::Display for InvalidGameData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid game: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidGameData {}
+
+/// Replays the history from the setup, and checks that it leads to the same board and turn.
+impl TryFrom<GameData> for Game {
+    type Error = InvalidGameData;
+
+    fn try_from(data: GameData) -> Result<Game, InvalidGameData> {
+        let num_players = data.board.num_players();
+        for &player in &[data.first_player, data.next_player] {
+            if (player as usize) >= num_players {
+                return Err(InvalidGameData(format!(
+                    "there is no player {} in a {} player game",
+                    player, num_players
+                )));
+            }
+        }
+
+        let mut start = data.board.clone();
+        for c in data.board.coords() {
+            start.clear(c);
+        }
+        data.setup
+            .place_on(&mut start)
+            .map_err(|(c, err)| InvalidGameData(format!("setup ({}): {}", c, err)))?;
+        let mut game = Game::new(start, data.first_player);
+        game.setup = data.setup;
+        for (move_index, &action) in data.history.iter().enumerate() {
+            game.play(action).map_err(|err| {
+                InvalidGameData(format!("move {} ({}): {}", move_index + 1, action, err))
+            })?;
+        }
+
+        if game.board != data.board {
+            return Err(InvalidGameData(
+                "the board doesn't match the moves".to_owned(),
+            ));
+        }
+        if game.next_player != data.next_player {
+            return Err(InvalidGameData(format!(
+                "it should be player {}'s turn, not {}'s",
+                game.next_player, data.next_player
+            )));
+        }
+        Ok(game)
+    }
+}
+
+impl Game {
+    /// Start a new game on the given board.
+    /// Any marks already on it are the starting position, which [`Game::unmake`] never removes.
+    pub fn new(board: Board, first_player: Player) -> Self {
+        assert!((first_player as usize) < board.num_players());
+        Game {
+            setup: Setup::from_board(&board),
+            board,
+            first_player,
+            next_player: first_player,
+            history: vec![],
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.board.num_players()
+    }
+
+    /// The marks that were on the board before the first move.
+    pub fn setup(&self) -> &Setup {
+        &self.setup
+    }
+
+    pub fn first_player(&self) -> Player {
+        self.first_player
+    }
+
+    /// Whose turn it is.
+    pub fn next_player(&self) -> Player {
+        self.next_player
+    }
+
+    /// Every action taken so far, in order.
+    pub fn history(&self) -> &[Action] {
+        &self.history
+    }
+
+    pub fn is_game_over(&self) -> bool {
+        self.board.is_game_over()
+    }
+
+    /// Who won and why, or `None` if the game isn't over yet.
+    pub fn result(&self) -> Option<GameResult> {
+        self.board.game_result()
+    }
+
+    /// The first mark, if the next player may take it over with [`Action::Swap`].
+    pub fn swappable(&self) -> Option<Coord> {
+        match self.history[..] {
+            [Action::Move(coord)] if self.board.rules().swap && !self.is_game_over() => Some(coord),
+            _ => None,
+        }
+    }
+
+    /// All actions the next player can take. Empty when the game is over.
+    pub fn valid_actions(&self) -> Vec<Action> {
+        if self.is_game_over() {
+            return vec![];
+        }
+        let mut actions: Vec<Action> = self
+            .board
+            .valid_moves(self.next_player)
+            .into_iter()
+            .map(Action::Move)
+            .collect();
+        if self.swappable().is_some() {
+            actions.push(Action::Swap);
+        }
+        if actions.is_empty() {
+            actions.push(Action::Pass);
+        }
+        actions
+    }
+
+    /// Would this be a valid action for the next player?
+    pub fn check(&self, action: Action) -> Result<(), HobogoError> {
+        if self.is_game_over() {
+            return Err(HobogoError::GameOver);
+        }
+        match action {
+            Action::Pass => {
+                if self.board.valid_moves(self.next_player).is_empty() && self.swappable().is_none()
+                {
+                    Ok(())
+                } else {
+                    Err(HobogoError::CannotPass)
+                }
+            }
+            Action::Move(coord) => self.board.check_move(coord, self.next_player),
+            Action::Swap => match self.swappable() {
+                Some(_) => Ok(()),
+                None => Err(HobogoError::CannotSwap),
+            },
+        }
+    }
+
+    /// Let the next player take the given action, if it is valid.
+    pub fn play(&mut self, action: Action) -> Result<(), HobogoError> {
+        self.check(action)?;
+        match action {
+            Action::Pass => {}
+            Action::Move(coord) => self.board.set(coord, self.next_player),
+            Action::Swap => self.board.set(self.swappable().unwrap(), self.next_player),
+        }
+        self.next_player = (self.next_player + 1) % (self.num_players() as Player);
+        self.history.push(action);
+        Ok(())
+    }
+
+    /// Let `player` take the given action, if it is their turn and the action is valid.
+    pub fn try_play(&mut self, player: Player, action: Action) -> Result<(), HobogoError> {
+        if (player as usize) >= self.num_players() {
+            return Err(HobogoError::NoSuchPlayer(player));
+        }
+        if player != self.next_player {
+            return Err(HobogoError::WrongPlayer {
+                expected: self.next_player,
+                got: player,
+            });
+        }
+        self.play(action)
+    }
+
+    /// Take back the last action. Returns it, or `None` if nothing has been played yet.
+    pub fn unmake(&mut self) -> Option<Action> {
+        let action = self.history.pop()?;
+        let num_players = self.num_players() as Player;
+        self.next_player = (self.next_player + num_players - 1) % num_players;
+        match action {
+            Action::Pass => {}
+            Action::Move(coord) => self.board.clear(coord),
+            Action::Swap => {
+                // Give the first mark back to whoever made it:
+                if let Some(Action::Move(coord)) = self.history.first() {
+                    self.board.set(*coord, self.first_player);
+                }
+            }
+        }
+        Some(action)
+    }
+
+    /// The game as seen by the AI.
+    pub fn mcts_state(&self) -> GameState {
+        GameState {
+            next_player: self.next_player,
+            board: self.board.clone(),
+            num_actions: self.history.len(),
+            swappable: self.swappable(),
+        }
+    }
+
+    /// Let the AI pick the next action. `None` when the game is over.
+    pub fn ai_action(&self) -> Option<Action> {
+        if self.is_game_over() {
+            return None;
+        }
+        ai_action(self.mcts_state())
+    }
+
+    /// Let the AI pick the next action, thinking for as long as the budget allows.
+    /// `None` when the game is over.
+    pub fn ai_action_with_budget(&self, budget: Budget) -> Option<Action> {
+        use rand::SeedableRng;
+        if self.is_game_over() {
+            return None;
+        }
+        think(self.mcts_state(), budget, &mut AiRng::from_entropy())
+    }
+
+    /// Like [`Game::ai_action`], but reproducible: the same game, seed and number of iterations
+    /// give the same action on every platform.
+    pub fn ai_action_seeded(&self, seed: u64, iterations: usize) -> Option<Action> {
+        if self.is_game_over() {
+            return None;
+        }
+        ai_action_seeded(self.mcts_state(), seed, iterations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::rules::RuleSet;
+
+    fn swap_game() -> Game {
+        let rules = RuleSet {
+            swap: true,
+            ..RuleSet::default()
+        };
+        Game::new(Board::new(5, 5, 2).with_rules(rules), 0)
+    }
+
+    /// Everything that should be restored by undoing a move.
+    fn snapshot(game: &Game) -> (Board, Player, Vec<Action>, Option<Coord>) {
+        (
+            game.board().clone(),
+            game.next_player(),
+            game.history().to_vec(),
+            game.swappable(),
+        )
+    }
+
+    #[test]
+    fn unmake_undoes_play() {
+        let mut rng = AiRng::seed_from_u64(3);
+        for _ in 0..20 {
+            let mut game = swap_game();
+            let mut snapshots = vec![];
+            while !game.is_game_over() {
+                let actions = game.valid_actions();
+                let action = actions[rng.gen_range(0, actions.len())];
+                snapshots.push(snapshot(&game));
+                game.play(action).unwrap();
+            }
+            while let Some(before) = snapshots.pop() {
+                game.unmake().unwrap();
+                assert!(snapshot(&game) == before);
+            }
+            assert_eq!(game.unmake(), None);
+        }
+    }
+
+    #[test]
+    fn swap_and_unmake() {
+        let mut game = swap_game();
+        let c = Coord::new(2, 2);
+        game.play(Action::Move(c)).unwrap();
+        assert_eq!(game.swappable(), Some(c));
+        assert!(game.valid_actions().contains(&Action::Swap));
+
+        game.play(Action::Swap).unwrap();
+        assert_eq!(game.board().at(c), Some(1));
+        assert_eq!(game.next_player(), 0);
+        assert_eq!(game.swappable(), None);
+        assert_eq!(game.check(Action::Swap), Err(HobogoError::CannotSwap));
+
+        assert_eq!(game.unmake(), Some(Action::Swap));
+        assert_eq!(game.board().at(c), Some(0));
+        assert_eq!(game.next_player(), 1);
+        assert_eq!(game.swappable(), Some(c));
+    }
+
+    #[test]
+    fn try_play_checks_the_player() {
+        let mut game = swap_game();
+        let action = Action::Move(Coord::new(0, 0));
+        assert_eq!(
+            game.try_play(1, action),
+            Err(HobogoError::WrongPlayer {
+                expected: 0,
+                got: 1
+            })
+        );
+        assert_eq!(game.try_play(2, action), Err(HobogoError::NoSuchPlayer(2)));
+        assert_eq!(game.try_play(0, action), Ok(()));
+        assert_eq!(game.next_player(), 1);
+        assert_eq!(
+            game.try_play(1, action),
+            Err(HobogoError::Occupied(Coord::new(0, 0)))
+        );
+        assert_eq!(game.history(), &[action]);
+    }
+
+    #[test]
+    fn deserialize_checks_the_game() {
+        let mut board = Board::new(5, 5, 2);
+        board.set(Coord::new(1, 1), 1);
+        let mut game = Game::new(board, 0);
+        for &action in &[
+            Action::Move(Coord::new(3, 3)),
+            Action::Move(Coord::new(3, 1)),
+        ] {
+            game.play(action).unwrap();
+        }
+        let json = serde_json::to_string(&game).unwrap();
+        let loaded: Game = serde_json::from_str(&json).unwrap();
+        assert!(loaded.board() == game.board());
+        assert_eq!(loaded.history(), game.history());
+        assert_eq!(loaded.setup(), game.setup());
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let load = |change: &dyn Fn(&mut serde_json::Value)| {
+            let mut value = value.clone();
+            change(&mut value);
+            serde_json::from_value::<Game>(value)
+                .err()
+                .map(|err| err.to_string())
+        };
+        assert_eq!(load(&|_| {}), None);
+        assert_eq!(
+            load(&|v| v["next_player"] = 7.into()),
+            Some("Invalid game: there is no player 7 in a 2 player game".to_owned())
+        );
+        assert_eq!(
+            load(&|v| v["first_player"] = 2.into()),
+            Some("Invalid game: there is no player 2 in a 2 player game".to_owned())
+        );
+        assert_eq!(
+            load(&|v| v["next_player"] = 1.into()),
+            Some("Invalid game: it should be player 0's turn, not 1's".to_owned())
+        );
+        assert_eq!(
+            load(&|v| v["history"].as_array_mut().unwrap().truncate(1)),
+            Some("Invalid game: the board doesn't match the moves".to_owned())
+        );
+        assert_eq!(
+            load(&|v| v["history"][1] = v["history"][0].clone()),
+            Some("Invalid game: move 2 (D4): D4 is already occupied".to_owned())
+        );
+    }
+}
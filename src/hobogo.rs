@@ -1,15 +1,35 @@
+use std::convert::TryFrom;
 use std::fmt;
 
-use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-use egui_web::now_sec;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
+use crate::ai;
+use crate::bitboard::{Bits, Counter};
+use crate::map::Map;
 use crate::mcts;
+use crate::rules::RuleSet;
+use crate::teams::{Team, Teams};
+use crate::topology::Topology;
 
-const MAX_PLAYERS: usize = 8;
-
+/// Players are numbered from 0.
 pub type Player = u8;
 
+/// The most players a game can have: one for each letter we write them with in diagrams
+/// (see [`player_char`]).
+///
+/// This limit is deliberate: one letter per player keeps diagrams and records readable,
+/// and 26 players is already far more than have room to move on any board we play on.
+/// [`PlayerSet`] relies on it.
+pub const MAX_PLAYERS: usize = PLAYER_CHARS.len();
+
+/// Can a game have this many players? At least two, and at most [`MAX_PLAYERS`].
+pub fn is_valid_num_players(num_players: usize) -> bool {
+    (2..=MAX_PLAYERS).contains(&num_players)
+}
+
 pub type Cell = Option<Player>;
 
 fn player_from_i8(x: i8) -> Cell {
@@ -20,30 +40,74 @@ fn player_from_i8(x: i8) -> Cell {
     }
 }
 
+/// A set of players, one bit each. Can hold up to [`MAX_PLAYERS`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PlayerSet(u32);
+
+const _: () = assert!(MAX_PLAYERS <= 32, "Too many players for a PlayerSet");
+
+impl PlayerSet {
+    pub fn insert(&mut self, player: Player) {
+        debug_assert!((player as usize) < MAX_PLAYERS);
+        self.0 |= 1 << player;
+    }
+
+    pub fn contains(&self, player: Player) -> bool {
+        (player as usize) < MAX_PLAYERS && self.0 & (1 << player) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Player> {
+        let set = *self;
+        (0..MAX_PLAYERS as Player).filter(move |&player| set.contains(player))
+    }
+}
+
+impl std::iter::FromIterator<Player> for PlayerSet {
+    fn from_iter<I: IntoIterator<Item = Player>>(iter: I) -> Self {
+        let mut set = PlayerSet::default();
+        for player in iter {
+            set.insert(player);
+        }
+        set
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Influence {
     // This player occupies this cell
     Occupied(Player),
 
-    // This player has so much influence that noone else can ever occupy this cell.
+    // This team has so much influence that noone else can ever occupy this cell.
     // TODO: remove now that we have volatile_cells
-    Ruled(Player),
+    Ruled(Team),
 
-    // The given player is currently the most influential here,
+    // The given team leads here by at least the claim margin of the rules,
     // so nobody can steal it *right away*.
-    Claimed(Player), // TODO: add a margin
+    // Also: how many more neighbors they have than the runner-up.
+    Claimed(Team, u8),
 
-    // Up for grabs
-    Tied,
+    // Nobody has a claim here.
+    // These teams have the same (largest) number of neighbors here.
+    // With a claim margin above 1 this can be a single team that doesn't lead by enough.
+    Tied(PlayerSet, u8),
 }
 
 impl Influence {
-    pub fn player(&self) -> Option<Player> {
+    /// The team that occupies, rules or claims the cell, if any.
+    pub fn team(&self, teams: &Teams) -> Option<Team> {
         match self {
-            Influence::Occupied(player) => Some(*player),
-            Influence::Ruled(player) => Some(*player),
-            Influence::Claimed(player) => Some(*player),
-            Influence::Tied => None,
+            Influence::Occupied(player) => Some(teams.team_of(*player)),
+            Influence::Ruled(team) => Some(*team),
+            Influence::Claimed(team, _) => Some(*team),
+            Influence::Tied(..) => None,
         }
     }
 
@@ -51,157 +115,1212 @@ impl Influence {
         match self {
             Influence::Occupied(_) => true,
             Influence::Ruled(_) => false,
-            Influence::Claimed(_) => false,
-            Influence::Tied => false,
+            Influence::Claimed(..) => false,
+            Influence::Tied(..) => false,
         }
     }
 }
 
-pub type Points = [usize; MAX_PLAYERS];
+/// Points of each team.
+pub type Points = Vec<usize>;
+
+/// Why a game ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameOverReason {
+    /// At most one team can still make a move.
+    /// If only occupied cells count, no team can.
+    NoMoves,
+    /// Nothing can change who gets which cell anymore.
+    Settled,
+    /// Every free cell is ruled by someone.
+    AllRuled,
+    /// One team is so far ahead that nobody can catch up.
+    UnbeatableLead,
+}
+
+/// The outcome of a finished game.
+///
+/// This is all about teams. Without teams, every player is a team of their own
+/// with the same number as the player.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameResult {
+    pub reason: GameOverReason,
+
+    /// The final points of each team.
+    pub points: Vec<usize>,
+
+    /// The teams grouped by placement, winners first.
+    /// Teams with the same number of points share a placement.
+    pub placements: Vec<Vec<Team>>,
+}
+
+impl GameResult {
+    pub fn new(reason: GameOverReason, points: Vec<usize>) -> Self {
+        let mut teams: Vec<Team> = (0..points.len()).map(|t| t as Team).collect();
+        teams.sort_by_key(|&team| std::cmp::Reverse(points[team as usize]));
+
+        let mut placements: Vec<Vec<Team>> = vec![];
+        for team in teams {
+            match placements.last_mut() {
+                Some(group) if points[group[0] as usize] == points[team as usize] => {
+                    group.push(team)
+                }
+                _ => placements.push(vec![team]),
+            }
+        }
+
+        GameResult {
+            reason,
+            points,
+            placements,
+        }
+    }
+
+    /// Everyone sharing the first place.
+    pub fn winners(&self) -> &[Team] {
+        &self.placements[0]
+    }
+
+    /// The winner, unless it is a tie.
+    pub fn winner(&self) -> Option<Team> {
+        match self.winners() {
+            [winner] => Some(*winner),
+            _ => None,
+        }
+    }
+
+    pub fn is_tie(&self) -> bool {
+        self.winners().len() > 1
+    }
+
+    /// 0 for the winner(s), 1 for the runner(s) up, etc.
+    pub fn placement_of(&self, team: Team) -> usize {
+        self.placements
+            .iter()
+            .position(|group| group.contains(&team))
+            .unwrap()
+    }
+}
 
 // ----------------------------------------------------------------------------
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Coord {
     pub x: i32,
     pub y: i32,
+    /// The layer. Always 0 on flat boards.
+    #[serde(default)]
+    pub z: i32,
 }
 
+impl Coord {
+    /// A cell on the first (or only) layer.
+    pub fn new(x: i32, y: i32) -> Coord {
+        Coord { x, y, z: 0 }
+    }
+}
+
+/// Spreadsheet-style column name: A, B, …, Z, AA, AB, …
+pub fn column_name(x: i32) -> String {
+    let mut name = Vec::new();
+    let mut n = x as u32 + 1;
+    while n > 0 {
+        n -= 1;
+        name.push(b'A' + (n % 26) as u8);
+        n /= 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+/// Row name, counting from 1.
+pub fn row_name(y: i32) -> String {
+    (y + 1).to_string()
+}
+
+/// Layer name, counting from 1.
+pub fn layer_name(z: i32) -> String {
+    (z + 1).to_string()
+}
+
+/// Written like `C4`, with the layer after a slash (`C4/2`) unless it is the first one.
 impl fmt::Display for Coord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}{}", ('A' as u8 + self.x as u8) as char, self.y + 1)
+        if self.x < 0 || self.y < 0 || self.z < 0 {
+            write!(f, "({}, {}, {})", self.x, self.y, self.z)
+        } else if self.z == 0 {
+            write!(f, "{}{}", column_name(self.x), row_name(self.y))
+        } else {
+            write!(
+                f,
+                "{}{}/{}",
+                column_name(self.x),
+                row_name(self.y),
+                layer_name(self.z)
+            )
+        }
+    }
+}
+
+/// Failed to parse a [`Coord`] or [`mcts::Action`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseCoordError(String);
+
+impl fmt::Display for ParseCoordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expected a cell like 'C4', got '{}'", self.0)
+    }
+}
+
+impl std::str::FromStr for Coord {
+    type Err = ParseCoordError;
+
+    /// Parses e.g. `C4`, `aa12` or `B2/3` (layer 3).
+    fn from_str(s: &str) -> Result<Coord, ParseCoordError> {
+        let error = || ParseCoordError(s.to_owned());
+
+        let (s, layer) = match s.find('/') {
+            Some(slash) => {
+                let layer: i32 = s[slash + 1..].parse().map_err(|_| error())?;
+                if layer < 1 {
+                    return Err(error());
+                }
+                (&s[..slash], layer)
+            }
+            None => (s, 1),
+        };
+
+        let num_letters = s.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+        let (letters, digits) = s.split_at(num_letters);
+        if letters.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(error());
+        }
+
+        let mut column: i32 = 0;
+        for letter in letters.bytes() {
+            let value = (letter.to_ascii_uppercase() - b'A') as i32 + 1;
+            column = column
+                .checked_mul(26)
+                .and_then(|column| column.checked_add(value))
+                .ok_or_else(error)?;
+        }
+
+        let row: i32 = digits.parse().map_err(|_| error())?;
+        if row < 1 {
+            return Err(error());
+        }
+
+        Ok(Coord {
+            x: column - 1,
+            y: row - 1,
+            z: layer - 1,
+        })
     }
 }
 
 // ----------------------------------------------------------------------------
 
-struct Neighbors {
-    board_size: (i32, i32),
+/// The cell itself, followed by all its neighbors.
+struct Neighbors<'a> {
+    topology: Topology,
+    /// Width, height and depth.
+    board_size: (i32, i32, i32),
+    stride: i32,
+    /// Neighbors must be in here.
+    cell_mask: &'a Bits,
     c: Coord,
-    index: i32,
+    index: usize,
 }
 
-impl Neighbors {
-    fn new(board_size: (i32, i32), c: Coord) -> Self {
+impl<'a> Neighbors<'a> {
+    fn new(
+        topology: Topology,
+        board_size: (i32, i32, i32),
+        stride: i32,
+        cell_mask: &'a Bits,
+        c: Coord,
+    ) -> Self {
         Neighbors {
+            topology,
             board_size,
+            stride,
+            cell_mask,
             c,
             index: 0,
         }
     }
 }
 
-impl Iterator for Neighbors {
+impl<'a> Iterator for Neighbors<'a> {
     type Item = Coord;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.index < 9 {
-            let dx = (self.index % 3) - 1;
-            let dy = (self.index / 3) - 1;
+        if self.index == 0 {
             self.index += 1;
-            let nc = Coord {
-                x: self.c.x + dx,
-                y: self.c.y + dy,
-            };
-            if 0 <= nc.x && nc.x < self.board_size.0 && 0 <= nc.y && nc.y < self.board_size.1 {
-                return Some(nc);
+            return Some(self.c);
+        }
+        let offsets = self.topology.offsets(self.board_size.2 > 1);
+        while self.index <= offsets.len() {
+            let i = self.index - 1;
+            self.index += 1;
+            if let Some(nc) = self
+                .topology
+                .distinct_step(self.board_size, self.c, offsets, i)
+            {
+                let bit = (self.stride * (self.board_size.1 * nc.z + nc.y) + nc.x) as usize;
+                if self.cell_mask.get(bit) {
+                    return Some(nc);
+                }
             }
         }
         None
     }
 }
 
+/// All coordinates of a `width` x `height` rectangle, row by row.
+fn rect_coords(width: i32, height: i32) -> impl Iterator<Item = Coord> {
+    (0..height).flat_map(move |y| (0..width).map(move |x| Coord::new(x, y)))
+}
+
+/// All coordinates of `depth` such rectangles, layer by layer.
+fn box_coords(width: i32, height: i32, depth: i32) -> impl Iterator<Item = Coord> {
+    (0..depth).flat_map(move |z| rect_coords(width, height).map(move |c| Coord { z, ..c }))
+}
+
 // ----------------------------------------------------------------------------
 
+/// Why a move could not be made.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HobogoError {
+    /// The coordinate is not on the board.
+    OutOfBounds(Coord),
+
+    /// Somebody already has a mark there.
+    Occupied(Coord),
+
+    /// An enemy has more neighbors around the cell than the player who wants to move there
+    /// (together with their team), by at least the claim margin of the rules.
+    Outnumbered {
+        coord: Coord,
+        /// How many neighbors the player who wants to move (and their team) has.
+        friends: u8,
+        /// The strongest enemy team around the cell.
+        enemy: Team,
+        /// How many neighbors that enemy has.
+        enemies: u8,
+    },
+
+    /// An enemy has as many neighbors around the cell as the player who wants to move there
+    /// (together with their team), and the rules say ties block.
+    Tied {
+        coord: Coord,
+        enemy: Team,
+        /// How many neighbors each of them has.
+        neighbors: u8,
+    },
+
+    /// There is no player with that number in this game.
+    NoSuchPlayer(Player),
+
+    /// It is somebody else's turn.
+    WrongPlayer {
+        /// Whose turn it is.
+        expected: Player,
+        /// Who tried to move.
+        got: Player,
+    },
+
+    /// Passing is only allowed when you have no valid move.
+    CannotPass,
+
+    /// Swapping is only allowed instead of the second move, and only if the rules say so.
+    CannotSwap,
+
+    /// No more moves can be made.
+    GameOver,
+}
+
+impl fmt::Display for HobogoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HobogoError::OutOfBounds(c) => write!(f, "{} is outside of the board", c),
+            HobogoError::Occupied(c) => write!(f, "{} is already occupied", c),
+            HobogoError::Outnumbered {
+                coord,
+                friends,
+                enemy,
+                enemies,
+            } => write!(
+                f,
+                "{} is outnumbered: enemy {} has {} neighbors there, but you only have {}",
+                coord, enemy, enemies, friends
+            ),
+            HobogoError::Tied {
+                coord,
+                enemy,
+                neighbors,
+            } => write!(
+                f,
+                "{} is contested: enemy {} has as many neighbors there as you ({})",
+                coord, enemy, neighbors
+            ),
+            HobogoError::NoSuchPlayer(player) => write!(f, "There is no player {}", player),
+            HobogoError::WrongPlayer { expected, got } => {
+                write!(f, "It is player {}:s turn, not player {}:s", expected, got)
+            }
+            HobogoError::CannotPass => write!(f, "You can only pass if you have no valid move"),
+            HobogoError::CannotSwap => write!(
+                f,
+                "You can only swap instead of the second move of the game"
+            ),
+            HobogoError::GameOver => write!(f, "The game is over"),
+        }
+    }
+}
+
+impl std::error::Error for HobogoError {}
+
+// ----------------------------------------------------------------------------
+
+/// One of the 8 rotations and reflections of a square board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    /// Quarter turn clockwise.
+    Rotate90,
+    Rotate180,
+    /// Quarter turn counter-clockwise.
+    Rotate270,
+    /// Mirror left-right.
+    FlipX,
+    /// Mirror up-down.
+    FlipY,
+    /// Mirror along the diagonal from A1.
+    Transpose,
+    /// Mirror along the other diagonal.
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipX,
+        Symmetry::FlipY,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    /// Does this swap the width and height of the board?
+    pub fn swaps_axes(self) -> bool {
+        match self {
+            Symmetry::Rotate90
+            | Symmetry::Rotate270
+            | Symmetry::Transpose
+            | Symmetry::AntiTranspose => true,
+            Symmetry::Identity | Symmetry::Rotate180 | Symmetry::FlipX | Symmetry::FlipY => false,
+        }
+    }
+
+    /// The symmetry that undoes this one.
+    pub fn inverse(self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            other => other,
+        }
+    }
+
+    /// Where does `c` end up, given that it is on a board of the given size?
+    /// The layer stays the same.
+    pub fn transform(self, c: Coord, width: i32, height: i32) -> Coord {
+        let Coord { x, y, z } = c;
+        let (x, y) = match self {
+            Symmetry::Identity => (x, y),
+            Symmetry::Rotate90 => (height - 1 - y, x),
+            Symmetry::Rotate180 => (width - 1 - x, height - 1 - y),
+            Symmetry::Rotate270 => (y, width - 1 - x),
+            Symmetry::FlipX => (width - 1 - x, y),
+            Symmetry::FlipY => (x, height - 1 - y),
+            Symmetry::Transpose => (y, x),
+            Symmetry::AntiTranspose => (height - 1 - y, width - 1 - x),
+        };
+        Coord { x, y, z }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A well-mixed pseudo-random number derived from `x`.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Zobrist key for `player` having a mark in the cell at `index`.
+fn zobrist_cell_key(index: usize, player: Player) -> u64 {
+    splitmix64(((index as u64) << 8) | player as u64)
+}
+
+/// Zobrist key for it being `player`:s turn.
+pub fn zobrist_turn_key(player: Player) -> u64 {
+    splitmix64(!(player as u64))
+}
+
+// ----------------------------------------------------------------------------
+
+/// The serialized form of a [`Board`].
 #[derive(Clone, Deserialize, Serialize)]
-pub struct Board {
+struct BoardData {
+    /// Layer by layer, row by row.
     cells: Vec<Cell>,
+    width: i32,
+    height: i32,
+    /// Number of layers. 0 means 1 (saved before we had layers).
+    #[serde(default)]
+    depth: i32,
+    #[serde(default)]
+    num_players: usize,
+    #[serde(default)]
+    rules: RuleSet,
+    #[serde(default)]
+    topology: Topology,
+    /// `None` for a full rectangle.
+    #[serde(default)]
+    map: Option<Map>,
+    /// `None` when everyone plays for themselves.
+    #[serde(default)]
+    teams: Option<Teams>,
+}
+
+/// Why a serialized [`Board`] could not be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidBoardData(String);
+
+impl fmt::Display for InvalidBoardData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid board: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidBoardData {}
+
+impl TryFrom<BoardData> for Board {
+    type Error = InvalidBoardData;
+
+    fn try_from(data: BoardData) -> Result<Board, InvalidBoardData> {
+        let depth = data.depth.max(1);
+        if data.width < 1 || data.height < 1 {
+            return Err(InvalidBoardData(format!(
+                "bad size {}x{}",
+                data.width, data.height
+            )));
+        }
+        let num_cells = data.width as usize * data.height as usize * depth as usize;
+        if data.cells.len() != num_cells {
+            return Err(InvalidBoardData(format!(
+                "{} cells for a {}x{}x{} board",
+                data.cells.len(),
+                data.width,
+                data.height,
+                depth
+            )));
+        }
+
+        let num_players = if data.num_players == 0 {
+            // Saved before we stored the number of players:
+            let max_player = data.cells.iter().filter_map(|&c| c).max();
+            max_player.map_or(2, |p| (p as usize + 1).max(2))
+        } else {
+            data.num_players
+        };
+        if !is_valid_num_players(num_players) {
+            return Err(InvalidBoardData(format!(
+                "bad number of players: {}",
+                num_players
+            )));
+        }
+
+        let map = match data.map {
+            Some(map) if map.width() != data.width || map.height() != data.height => {
+                return Err(InvalidBoardData(format!(
+                    "{}x{} map for a {}x{} board",
+                    map.width(),
+                    map.height(),
+                    data.width,
+                    data.height
+                )));
+            }
+            Some(map) => map,
+            None => Map::full(data.width, data.height),
+        };
+
+        let teams = match data.teams {
+            Some(teams) if teams.num_players() != num_players => {
+                return Err(InvalidBoardData(format!(
+                    "teams for {} players in a {} player game",
+                    teams.num_players(),
+                    num_players
+                )));
+            }
+            Some(teams) => teams,
+            None => Teams::solo(num_players),
+        };
+
+        let mut board = Board::from_map(&map, num_players)
+            .with_layers(depth)
+            .with_topology(data.topology)
+            .with_rules(data.rules)
+            .with_teams(teams);
+        for (c, cell) in box_coords(data.width, data.height, depth).zip(data.cells) {
+            if let Some(player) = cell {
+                board
+                    .try_set(c, player)
+                    .map_err(|err| InvalidBoardData(format!("cell {}: {}", c, err)))?;
+            }
+        }
+        Ok(board)
+    }
+}
+
+impl From<Board> for BoardData {
+    fn from(board: Board) -> BoardData {
+        BoardData {
+            cells: box_coords(board.width, board.height, board.depth)
+                .map(|c| board.at(c))
+                .collect(),
+            width: board.width,
+            height: board.height,
+            depth: board.depth,
+            num_players: board.num_players,
+            rules: board.rules,
+            topology: board.topology,
+            map: Some(board.map()).filter(|map| !map.is_full()),
+            teams: Some((*board.teams).clone()).filter(|teams| !teams.is_solo()),
+        }
+    }
+}
+
+/// The game board, stored as one bitset per player.
+///
+/// Bit `(z * height + y) * stride + x` represents the cell at `(x, y)` of layer `z`.
+/// Each layer covers a `width` x `height` rectangle, but some cells of it may be blocked (see [`Map`]).
+/// Each row is followed by one unused padding bit so that shifting
+/// a bitset one step sideways never wraps around to another row.
+/// Boards that do wrap around (see [`Topology`]) shift the edge cells separately.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(try_from = "BoardData", into = "BoardData")]
+pub struct Board {
     pub width: i32,
     pub height: i32,
+    /// Number of layers. 1 for flat boards.
+    pub depth: i32,
+    num_players: usize,
+    rules: RuleSet,
+    topology: Topology,
+    teams: Arc<Teams>,
+    stride: i32,
+    /// The bits that represent actual cells (i.e. no padding and no blocked cells).
+    cell_mask: Arc<Bits>,
+    /// Moving every bit in `bits` by `shift` takes each cell in `bits` to one of its neighbors.
+    /// Together these cover every pair of neighbors exactly once.
+    neighbor_shifts: Arc<Vec<(isize, Bits)>>,
+    /// One bitset per player, marking the cells they occupy.
+    stones: Vec<Bits>,
+    /// `tallies[index * num_teams + team]` is the number of cells
+    /// `team` has in the cell at `index` and its neighbors.
+    /// Kept up-to-date by [`Board::set`].
+    tallies: Vec<u8>,
+    /// The number of unoccupied cells among each cell and its neighbors.
+    empty_neighbors: Vec<u8>,
+    /// Zobrist hash of all marks on the board. Kept up-to-date by [`Board::set`].
+    zobrist: u64,
 }
 
-impl std::ops::Index<Coord> for Board {
-    type Output = Cell;
-    fn index(&self, c: Coord) -> &Cell {
-        &self.cells[self.index(c).unwrap()]
+impl PartialEq for Board {
+    fn eq(&self, other: &Board) -> bool {
+        self.zobrist == other.zobrist
+            && self.width == other.width
+            && self.height == other.height
+            && self.depth == other.depth
+            && self.num_players == other.num_players
+            && self.rules == other.rules
+            && self.topology == other.topology
+            && self.teams == other.teams
+            && self.cell_mask == other.cell_mask
+            && self.stones == other.stones
     }
 }
 
-impl std::ops::IndexMut<Coord> for Board {
-    fn index_mut(&mut self, c: Coord) -> &mut Cell {
-        let index = self.index(c).unwrap();
-        &mut self.cells[index]
+impl Eq for Board {}
+
+impl std::hash::Hash for Board {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.zobrist);
     }
 }
 
 impl Board {
-    pub fn new(width: i32, height: i32) -> Board {
-        Board {
+    /// An empty rectangular board, bounded by its edges.
+    pub fn new(width: i32, height: i32, num_players: usize) -> Board {
+        Board::from_map(&Map::full(width, height), num_players)
+    }
+
+    /// An empty board of the given shape.
+    pub fn from_map(map: &Map, num_players: usize) -> Board {
+        Board::empty(map, 1, num_players, Topology::SQUARE)
+    }
+
+    /// `depth` layers, all with the given map.
+    fn empty(map: &Map, depth: i32, num_players: usize, topology: Topology) -> Board {
+        assert!(
+            num_players <= MAX_PLAYERS,
+            "Too many players: {}",
+            num_players
+        );
+        let width = map.width();
+        let height = map.height();
+        let stride = width + 1;
+        let num_bits = (stride * height * depth) as usize;
+        let bit = |c: Coord| stride * (height * c.z + c.y) + c.x;
+
+        let mut cell_mask = Bits::new(num_bits);
+        for c in box_coords(width, height, depth) {
+            if map.contains(c) {
+                cell_mask.insert(bit(c) as usize);
+            }
+        }
+
+        let mut neighbor_shifts: Vec<(isize, Bits)> = vec![];
+        for c in box_coords(width, height, depth) {
+            if !map.contains(c) {
+                continue;
+            }
+            let offsets = topology.offsets(depth > 1);
+            for i in 0..offsets.len() {
+                let to = topology.distinct_step((width, height, depth), c, offsets, i);
+                if let Some(to) = to.filter(|&to| map.contains(to)) {
+                    let shift = (bit(to) - bit(c)) as isize;
+                    match neighbor_shifts.iter_mut().find(|(s, _)| *s == shift) {
+                        Some((_, bits)) => bits.insert(bit(c) as usize),
+                        None => {
+                            let mut bits = Bits::new(num_bits);
+                            bits.insert(bit(c) as usize);
+                            neighbor_shifts.push((shift, bits));
+                        }
+                    }
+                }
+            }
+        }
+
+        let num_cells = (width * height * depth) as usize;
+        let mut board = Board {
             width,
             height,
-            cells: vec![None; (width * height) as usize],
+            depth,
+            num_players,
+            rules: RuleSet::default(),
+            topology,
+            teams: Arc::new(Teams::solo(num_players)),
+            stride,
+            cell_mask: Arc::new(cell_mask),
+            neighbor_shifts: Arc::new(neighbor_shifts),
+            stones: vec![Bits::new(num_bits); num_players],
+            tallies: vec![0; num_cells * num_players],
+            empty_neighbors: Vec::new(),
+            zobrist: 0,
+        };
+        board.empty_neighbors = box_coords(width, height, depth)
+            .map(|c| {
+                if board.contains(c) {
+                    board.neighbors_to(c).count() as u8
+                } else {
+                    0
+                }
+            })
+            .collect();
+        board
+    }
+
+    /// The same board, played with other rules.
+    pub fn with_rules(mut self, rules: RuleSet) -> Board {
+        self.rules = rules;
+        self
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.num_players
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// The same board, with the players split into teams.
+    pub fn with_teams(mut self, teams: Teams) -> Board {
+        assert_eq!(
+            teams.num_players(),
+            self.num_players,
+            "Teams for the wrong number of players"
+        );
+        if teams == *self.teams {
+            return self;
+        }
+        self.teams = Arc::new(teams);
+        let occupied: Vec<(Coord, Player)> = self
+            .coords()
+            .filter_map(|c| Some((c, self.at(c)?)))
+            .collect();
+        // Recount the tallies by placing all marks again (on top of themselves, as far as
+        // the number of empty neighbors goes, so those have to be put back afterwards).
+        let empty_neighbors = self.empty_neighbors.clone();
+        self.tallies = vec![0; empty_neighbors.len() * self.num_teams()];
+        for (c, player) in occupied {
+            self.update_tallies(c, player, 1);
+        }
+        self.empty_neighbors = empty_neighbors;
+        self
+    }
+
+    pub fn teams(&self) -> &Teams {
+        &self.teams
+    }
+
+    pub fn num_teams(&self) -> usize {
+        self.teams.num_teams()
+    }
+
+    /// The same board, with other neighbors.
+    pub fn with_topology(self, topology: Topology) -> Board {
+        if topology == self.topology {
+            return self;
+        }
+        self.rebuilt(self.depth, topology)
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    /// The same board, but with `depth` layers stacked on top of each other.
+    /// All layers have the same map. Marks on the removed layers (if any) are lost.
+    pub fn with_layers(self, depth: i32) -> Board {
+        assert!(depth >= 1, "A board needs at least one layer");
+        if depth == self.depth {
+            return self;
+        }
+        self.rebuilt(depth, self.topology)
+    }
+
+    fn rebuilt(&self, depth: i32, topology: Topology) -> Board {
+        let mut board = Board::empty(&self.map(), depth, self.num_players, topology)
+            .with_rules(self.rules)
+            .with_teams((*self.teams).clone());
+        for c in self.coords() {
+            if let Some(player) = self.at(c) {
+                if board.contains(c) {
+                    board.set(c, player);
+                }
+            }
+        }
+        board
+    }
+
+    /// Which cells of the rectangle are part of the board (on every layer).
+    pub fn map(&self) -> Map {
+        let mut map = Map::full(self.width, self.height);
+        for c in rect_coords(self.width, self.height) {
+            if !self.contains(c) {
+                map.block(c);
+            }
         }
+        map
+    }
+
+    /// A 64-bit hash identifying which marks are where.
+    pub fn zobrist(&self) -> u64 {
+        self.zobrist
     }
 
     pub fn is_empty(&self) -> bool {
-        !self.cells.iter().any(|c| c.is_some())
+        self.stones.iter().all(|stones| stones.is_empty())
     }
 
+    /// Is this a cell of the board (and not a blocked one)?
     pub fn contains(&self, c: Coord) -> bool {
-        0 <= c.x && c.x < self.width && 0 <= c.y && c.y < self.height
+        0 <= c.x
+            && c.x < self.width
+            && 0 <= c.y
+            && c.y < self.height
+            && 0 <= c.z
+            && c.z < self.depth
+            && self.cell_mask.get(self.bit(c))
+    }
+
+    pub fn index(&self, c: Coord) -> Option<usize> {
+        if self.contains(c) {
+            Some((self.width * (self.height * c.z + c.y) + c.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn bit(&self, c: Coord) -> usize {
+        (self.stride * (self.height * c.z + c.y) + c.x) as usize
+    }
+
+    fn coord_of_bit(&self, bit: usize) -> Coord {
+        let row = bit as i32 / self.stride;
+        Coord {
+            x: bit as i32 % self.stride,
+            y: row % self.height,
+            z: row / self.height,
+        }
+    }
+
+    pub fn at(&self, c: Coord) -> Cell {
+        if !self.contains(c) {
+            return None;
+        }
+        let bit = self.bit(c);
+        (0..self.num_players)
+            .find(|&player| self.stones[player].get(bit))
+            .map(|player| player as Player)
+    }
+
+    /// Put a mark for `player` at `c`, replacing whatever was there.
+    /// Does not check the rules.
+    ///
+    /// Panics if `c` is outside the board or there is no such player.
+    /// See [`Board::try_set`] for a version that doesn't.
+    pub fn set(&mut self, c: Coord, player: Player) {
+        assert!(
+            (player as usize) < self.num_players,
+            "There is no player {}",
+            player
+        );
+        self.clear(c);
+        let bit = self.bit(c);
+        self.stones[player as usize].insert(bit);
+        self.update_tallies(c, player, 1);
+        self.zobrist ^= zobrist_cell_key(self.index(c).unwrap(), player);
+    }
+
+    /// Put a mark for `player` in the empty cell at `c`, without checking the rules.
+    /// Useful for setting up positions.
+    pub fn try_set(&mut self, c: Coord, player: Player) -> Result<(), HobogoError> {
+        if !self.contains(c) {
+            return Err(HobogoError::OutOfBounds(c));
+        }
+        if (player as usize) >= self.num_players {
+            return Err(HobogoError::NoSuchPlayer(player));
+        }
+        if self.at(c).is_some() {
+            return Err(HobogoError::Occupied(c));
+        }
+        self.set(c, player);
+        Ok(())
+    }
+
+    /// Let `player` move to `c`, if the rules allow it.
+    ///
+    /// This does not know whose turn it is – use [`crate::game::Game`] for that.
+    pub fn try_play(&mut self, c: Coord, player: Player) -> Result<(), HobogoError> {
+        if self.is_game_over() {
+            return Err(HobogoError::GameOver);
+        }
+        self.check_move(c, player)?;
+        self.set(c, player);
+        Ok(())
+    }
+
+    /// Remove the mark at `c`, if any.
+    pub fn clear(&mut self, c: Coord) {
+        assert!(self.contains(c), "{} is outside the board", c);
+        if let Some(previous) = self.at(c) {
+            let bit = self.bit(c);
+            self.stones[previous as usize].remove(bit);
+            self.update_tallies(c, previous, -1);
+            self.zobrist ^= zobrist_cell_key(self.index(c).unwrap(), previous);
+        }
+    }
+
+    /// Someone added or removed a mark at `c`, so update the area around it.
+    fn update_tallies(&mut self, c: Coord, player: Player, delta: i8) {
+        let num_teams = self.num_teams();
+        let team = self.teams.team_of(player);
+        // Not `self.neighbors_to(c)`, so that we can borrow `cell_mask` while changing the tallies.
+        let neighbors = Neighbors::new(
+            self.topology,
+            (self.width, self.height, self.depth),
+            self.stride,
+            &self.cell_mask,
+            c,
+        );
+        for neighbor_coord in neighbors {
+            let ix = self.index(neighbor_coord).unwrap();
+            let tally = &mut self.tallies[ix * num_teams + team as usize];
+            *tally = (*tally as i8 + delta) as u8;
+            let empty = &mut self.empty_neighbors[ix];
+            *empty = (*empty as i8 - delta) as u8;
+        }
+    }
+
+    /// Compare the cached tallies to a full recount.
+    #[cfg(test)]
+    fn tallies_are_consistent(&self) -> bool {
+        self.coords().all(|c| {
+            let ix = self.index(c).unwrap();
+            let (influences, empty_neighbors) = self.count_neighbors_of(c);
+            let num_teams = self.num_teams();
+            influences[..] == self.tallies[ix * num_teams..(ix + 1) * num_teams]
+                && empty_neighbors == self.empty_neighbors[ix]
+        })
+    }
+
+    /// All cells of the board, except the blocked ones.
+    pub fn coords(&self) -> impl Iterator<Item = Coord> + '_ {
+        box_coords(self.width, self.height, self.depth).filter(move |&c| self.contains(c))
+    }
+
+    /// A copy of this board with all marks moved by the given symmetry.
+    pub fn transformed(&self, symmetry: Symmetry) -> Board {
+        let map = self.map().transformed(symmetry);
+        let mut board = Board::empty(&map, self.depth, self.num_players, self.topology)
+            .with_rules(self.rules)
+            .with_teams((*self.teams).clone());
+        for c in self.coords() {
+            if let Some(player) = self.at(c) {
+                board.set(symmetry.transform(c, self.width, self.height), player);
+            }
+        }
+        board
+    }
+
+    /// The symmetries that map this board onto itself.
+    /// All eight for square boards, otherwise only the four that keep the dimensions.
+    /// Fewer still if the neighborhood (e.g. on hex boards) or the map isn't that symmetric.
+    pub fn symmetries(&self) -> impl Iterator<Item = Symmetry> {
+        let is_square = self.width == self.height;
+        let topology = self.topology;
+        let map = self.map();
+        Symmetry::ALL.iter().copied().filter(move |&symmetry| {
+            (is_square || !symmetry.swaps_axes())
+                && topology.allows(symmetry)
+                && (map.is_full() || map.transformed(symmetry) == map)
+        })
+    }
+
+    /// The same representative of all boards that are rotations or reflections of each other,
+    /// together with the symmetry that turns `self` into it.
+    pub fn canonical(&self) -> (Board, Symmetry) {
+        let mut best: Option<(Vec<Cell>, Board, Symmetry)> = None;
+        for symmetry in self.symmetries() {
+            let board = self.transformed(symmetry);
+            let cells: Vec<Cell> = board.coords().map(|c| board.at(c)).collect();
+            if best
+                .as_ref()
+                .is_none_or(|(best_cells, _, _)| cells < *best_cells)
+            {
+                best = Some((cells, board, symmetry));
+            }
+        }
+        let (_, board, symmetry) = best.unwrap();
+        (board, symmetry)
+    }
+
+    fn neighbors_to(&self, c: Coord) -> Neighbors<'_> {
+        Neighbors::new(
+            self.topology,
+            (self.width, self.height, self.depth),
+            self.stride,
+            &self.cell_mask,
+            c,
+        )
+    }
+
+    /// All occupied cells.
+    fn occupied(&self) -> Bits {
+        let mut occupied = Bits::new(self.cell_mask.len());
+        for stones in &self.stones {
+            occupied |= stones;
+        }
+        occupied
+    }
+
+    /// How many cells nobody has a mark in yet.
+    pub fn num_free_cells(&self) -> usize {
+        self.cell_mask.count_ones() - self.occupied().count_ones()
+    }
+
+    /// All cells nobody has marked yet.
+    fn free(&self) -> Bits {
+        let mut free = (*self.cell_mask).clone();
+        free.and_not_assign(&self.occupied());
+        free
+    }
+
+    /// For each cell, how many of its neighbors are in the given set?
+    fn count_neighbors(&self, bits: &Bits) -> Counter {
+        let mut counter = Counter::new(bits.len());
+        for (shift, from) in self.neighbor_shifts.iter() {
+            let mut moving = bits.clone();
+            moving &= from;
+            counter.add(&moving.shifted(*shift));
+        }
+        counter
+    }
+
+    /// All cells occupied by members of the team.
+    fn team_stones(&self, team: Team) -> Bits {
+        let mut stones = Bits::new(self.cell_mask.len());
+        for player in self.teams.members(team) {
+            stones |= &self.stones[player as usize];
+        }
+        stones
+    }
+
+    /// Per team: the number of neighbors they have around each cell.
+    fn neighbor_counts(&self) -> Vec<Counter> {
+        (0..self.num_teams())
+            .map(|team| self.count_neighbors(&self.team_stones(team as Team)))
+            .collect()
+    }
+
+    /// `count` plus the claim margin minus one.
+    /// Where `a` is greater than this of `b`, `a` leads `b` by at least the claim margin.
+    fn short_of_claim(&self, count: &Counter) -> Counter {
+        let mut count = count.clone();
+        for _ in 1..self.rules.claim_margin {
+            count.add(&self.cell_mask);
+        }
+        count
     }
 
-    pub fn index(&self, c: Coord) -> Option<usize> {
-        if self.contains(c) {
-            Some((self.width * c.y + c.x) as usize)
-        } else {
-            None
+    /// The free cells `team` claims: where they lead everyone else by the claim margin.
+    fn leads(&self, counts: &[Counter], team: Team) -> Bits {
+        let mut leads = self.free();
+        for (other, other_count) in counts.iter().enumerate() {
+            if other != team as usize {
+                leads &= &counts[team as usize].greater_than(&self.short_of_claim(other_count));
+            }
         }
+        leads
     }
 
-    pub fn at(&self, c: Coord) -> Cell {
-        self.index(c).and_then(|index| self.cells[index])
+    /// All cells where a member of `team` could make a move right now.
+    fn valid_move_mask(&self, counts: &[Counter], team: Team) -> Bits {
+        let friends = &counts[team as usize];
+        let claim_threshold = self.short_of_claim(friends);
+        let mut valid = self.free();
+        for (other, other_count) in counts.iter().enumerate() {
+            if other != team as usize {
+                valid.and_not_assign(&other_count.greater_than(&claim_threshold));
+                if !self.rules.ties_allow_placement {
+                    let mut tied = other_count.equal_to(friends);
+                    tied &= &friends.nonzero();
+                    valid.and_not_assign(&tied);
+                }
+            }
+        }
+        valid
     }
 
-    pub fn set(&mut self, c: Coord, player: Player) {
-        let index = self.index(c).unwrap();
-        self.cells[index] = Some(player);
+    /// All cells where `player` could make a move right now.
+    pub fn valid_moves(&self, player: Player) -> Vec<Coord> {
+        let counts = self.neighbor_counts();
+        self.valid_move_mask(&counts, self.teams.team_of(player))
+            .iter_ones()
+            .map(|bit| self.coord_of_bit(bit))
+            .collect()
     }
 
-    pub fn coords(&self) -> impl Iterator<Item = Coord> {
-        let width = self.width;
-        let height = self.height;
-        (0..height).flat_map(move |y| (0..width).map(move |x| Coord { x, y }))
+    /// Pick a valid move for `player` uniformly at random.
+    pub fn random_valid_move<R: Rng>(&self, rng: &mut R, player: Player) -> Option<Coord> {
+        let counts = self.neighbor_counts();
+        let valid = self.valid_move_mask(&counts, self.teams.team_of(player));
+        let num_valid = valid.count_ones();
+        if num_valid == 0 {
+            None
+        } else {
+            // Not `usize`, which would pick differently on 32-bit platforms (like wasm):
+            let bit = valid
+                .nth_one(rng.gen_range(0, num_valid as u32) as usize)
+                .unwrap();
+            Some(self.coord_of_bit(bit))
+        }
     }
 
-    fn neighbors_to(&self, c: Coord) -> Neighbors {
-        Neighbors::new((self.width, self.height), c)
-    }
+    /// Like [`Board::is_valid_move`], but tells you what is wrong.
+    pub fn check_move(&self, c: Coord, who_wants_to_move: Player) -> Result<(), HobogoError> {
+        if !self.contains(c) {
+            return Err(HobogoError::OutOfBounds(c));
+        }
+        if (who_wants_to_move as usize) >= self.num_players {
+            return Err(HobogoError::NoSuchPlayer(who_wants_to_move));
+        }
+        if self.at(c).is_some() {
+            return Err(HobogoError::Occupied(c));
+        }
 
-    pub fn is_valid_move(&self, c: Coord, who_wants_to_move: Player, num_players: usize) -> bool {
-        if let Some(_) = self.at(c) {
-            return false;
+        let team = self.teams.team_of(who_wants_to_move);
+        let counts = self.neighbor_counts();
+        if self.valid_move_mask(&counts, team).get(self.bit(c)) {
+            return Ok(());
         }
 
+        // Find out which enemy is in the way:
         let (influences, _) = self.tally_neighbors(c);
-
-        for player in 0..num_players {
-            if influences[player] > influences[who_wants_to_move as usize] {
-                return false;
-            }
+        let friends = influences[team as usize];
+        let mut enemies = (0..self.num_teams()).filter(|&other| other != team as usize);
+        let (enemy, most_enemies) = enemies
+            .clone()
+            .map(|other| (other, influences[other]))
+            .max_by_key(|&(_, enemies)| enemies)
+            .expect("There is always more than one team");
+        if most_enemies as u32 >= friends as u32 + self.rules.claim_margin as u32 {
+            Err(HobogoError::Outnumbered {
+                coord: c,
+                friends,
+                enemy: enemy as Team,
+                enemies: most_enemies,
+            })
+        } else {
+            let tied_enemy = enemies
+                .find(|&other| influences[other] == friends)
+                .expect("Only outnumbered or tied cells are invalid");
+            Err(HobogoError::Tied {
+                coord: c,
+                enemy: tied_enemy as Team,
+                neighbors: friends,
+            })
         }
+    }
+
+    pub fn is_valid_move(&self, c: Coord, who_wants_to_move: Player) -> bool {
+        self.check_move(c, who_wants_to_move).is_ok()
+    }
 
-        true
+    /// How many marks each team has around `c` (including `c` itself),
+    /// and how many of the cells there are empty.
+    pub fn tally_neighbors(&self, c: Coord) -> (&[u8], u8) {
+        let ix = self.index(c).unwrap();
+        let num_teams = self.num_teams();
+        let influences = &self.tallies[ix * num_teams..(ix + 1) * num_teams];
+        (influences, self.empty_neighbors[ix])
     }
 
-    pub fn tally_neighbors(&self, c: Coord) -> ([u8; MAX_PLAYERS], u8) {
-        let mut influences: [u8; MAX_PLAYERS] = [0; MAX_PLAYERS];
-        let mut _num_neighbors = 0;
+    /// Like [`Board::tally_neighbors`], but without using the cache.
+    fn count_neighbors_of(&self, c: Coord) -> (Vec<u8>, u8) {
+        let mut influences = vec![0; self.num_teams()];
         let mut empty_neighbors = 0;
         for neighbor_coord in self.neighbors_to(c) {
-            _num_neighbors += 1;
             if let Some(player) = self.at(neighbor_coord) {
-                influences[player as usize] += 1;
+                influences[self.teams.team_of(player) as usize] += 1;
             } else {
                 empty_neighbors += 1;
             }
@@ -211,14 +1330,16 @@ impl Board {
     }
 
     /// Returns which cells could still change color:
-    pub fn volatile_cells(&self, num_players: usize) -> Vec<bool> {
-        let n = self.cells.len();
+    pub fn volatile_cells(&self) -> Vec<bool> {
+        let num_teams = self.num_teams();
+        let n = (self.width * self.height * self.depth) as usize;
 
-        // Player who currently claims this cell
+        // Team that currently claims this cell
         let mut claimed_by = vec![None; n];
 
-        // By how much does this cell have a mjaority?
-        let mut strengths: Vec<i8> = vec![0; n];
+        // By how much does this cell have a mjaority (beyond what is needed to claim it)?
+        // Blocked cells never change.
+        let mut strengths: Vec<i32> = vec![i32::MAX; n];
 
         // Coordinates we will pretend to change the player of:
         let mut flip_stack = Vec::new();
@@ -226,30 +1347,31 @@ impl Board {
         for c in self.coords() {
             let ix = self.index(c).unwrap();
             if let Some(player) = self.at(c) {
-                claimed_by[ix] = Some(player);
-                strengths[ix] = std::i8::MAX;
+                claimed_by[ix] = Some(self.teams.team_of(player));
+                strengths[ix] = i32::MAX;
             } else {
                 let (influences, _) = self.tally_neighbors(c);
-                let max_player = (0..num_players).max_by_key(|p| influences[*p]).unwrap();
+                let max_team = (0..num_teams).max_by_key(|t| influences[*t]).unwrap();
 
-                // Our lead over the other player:
-                let mut strength = std::i8::MAX;
+                // Our lead over the other teams:
+                let mut strength = i32::MAX;
 
-                for other_player in 0..num_players {
-                    if other_player != max_player {
-                        strength =
-                            strength.min((influences[max_player] - influences[other_player]) as i8);
+                for other_team in 0..num_teams {
+                    if other_team != max_team {
+                        strength = strength
+                            .min(influences[max_team] as i32 - influences[other_team] as i32);
                     }
                 }
+                let strength = strength - (self.rules.claim_margin as i32 - 1);
 
                 strengths[ix] = strength;
 
-                if strength == 0 {
+                if strength <= 0 {
                     // Tied:
                     claimed_by[ix] = None;
                     flip_stack.push(c);
                 } else {
-                    claimed_by[ix] = Some(max_player as Player);
+                    claimed_by[ix] = Some(max_team as Team);
                 }
             }
         }
@@ -261,8 +1383,8 @@ impl Board {
                 if let Some(claimer) = claimed_by[ix] {
                     for neighbor_coord in self.neighbors_to(coord) {
                         let neighbor_ix = self.index(neighbor_coord).unwrap();
-                        if let Some(neighbor_player) = claimed_by[neighbor_ix] {
-                            if neighbor_player != claimer {
+                        if let Some(neighbor_team) = claimed_by[neighbor_ix] {
+                            if neighbor_team != claimer {
                                 strengths[neighbor_ix] -= 1;
                                 if strengths[neighbor_ix] == 0 {
                                     flip_stack.push(neighbor_coord);
@@ -284,13 +1406,13 @@ impl Board {
                 continue;
             }
             visited[ix] = true;
-            let flip_player = claimed_by[ix];
+            let flip_team = claimed_by[ix];
 
             // Simulate this flipping this cell by weakening the neighbors:
             for neighbor_coord in self.neighbors_to(coord) {
                 let neighbor_ix = self.index(neighbor_coord).unwrap();
-                if let Some(neighbor_player) = claimed_by[neighbor_ix] {
-                    if flip_player.is_none() || flip_player.unwrap() == neighbor_player {
+                if let Some(neighbor_team) = claimed_by[neighbor_ix] {
+                    if flip_team.is_none() || flip_team.unwrap() == neighbor_team {
                         strengths[neighbor_ix] -= 1;
                         if strengths[neighbor_ix] == 0 {
                             flip_stack.push(neighbor_coord);
@@ -309,56 +1431,53 @@ impl Board {
         }
 
         let (influences, empty_neighbors) = self.tally_neighbors(c);
+        let margin = self.rules.claim_margin as u32;
 
         // Check if we have a ruler:
-        for player in 0..MAX_PLAYERS {
-            let mut other_player_can_take_this = false;
-            let mut other_player_is_as_influential = false;
+        for team in 0..self.num_teams() {
+            let mut other_team_can_take_this = false;
+            let mut other_team_is_as_influential = false;
 
-            for other_player in 0..MAX_PLAYERS {
-                if other_player == player {
+            for other_team in 0..self.num_teams() {
+                if other_team == team {
                     continue;
                 }
-                if influences[other_player] + empty_neighbors >= influences[player] {
-                    other_player_can_take_this = true;
+                let theirs = influences[other_team] as u32;
+                let ours = influences[team] as u32;
+                if theirs + empty_neighbors as u32 + margin > ours {
+                    other_team_can_take_this = true;
                 }
-                if influences[other_player] >= influences[player] {
-                    other_player_is_as_influential = true;
+                if theirs + margin > ours {
+                    other_team_is_as_influential = true;
                 }
             }
 
-            if !other_player_can_take_this {
-                return Influence::Ruled(player as Player);
+            if !other_team_can_take_this {
+                return Influence::Ruled(team as Team);
             }
-            if !other_player_is_as_influential {
-                return Influence::Claimed(player as Player);
+            if !other_team_is_as_influential {
+                let runner_up = (0..self.num_teams())
+                    .filter(|&other_team| other_team != team)
+                    .map(|other_team| influences[other_team])
+                    .max()
+                    .unwrap_or(0);
+                return Influence::Claimed(team as Team, influences[team] - runner_up);
             }
         }
-        // TODO: return which players are tied
-        Influence::Tied
+
+        let most = *influences.iter().max().unwrap();
+        let tied = (0..self.num_teams())
+            .filter(|&team| influences[team] == most)
+            .map(|team| team as Team)
+            .collect();
+        Influence::Tied(tied, most)
     }
 
-    fn more_than_one_player_has_valid_move(&self, num_players: usize) -> bool {
-        assert!(
-            num_players <= MAX_PLAYERS,
-            "Too many players: {}",
-            num_players
-        );
-        let mut num_moves = [0; MAX_PLAYERS];
-        for influence in self.coords().map(|c| self.influence(c)) {
-            match influence {
-                Influence::Occupied(_) => (),
-                Influence::Ruled(player) => num_moves[player as usize] += 1,
-                Influence::Claimed(player) => num_moves[player as usize] += 1,
-                Influence::Tied => {
-                    return true;
-                }
-            }
-        }
-        let num_players_with_valid_moves = (0..num_players)
-            .filter(|&player| num_moves[player] > 0)
-            .count();
-        num_players_with_valid_moves > 1
+    fn num_teams_with_valid_moves(&self) -> usize {
+        let counts = self.neighbor_counts();
+        (0..self.num_teams())
+            .filter(|&team| !self.valid_move_mask(&counts, team as Team).is_empty())
+            .count()
     }
 
     fn everything_is_ruled_by_someone(&self) -> bool {
@@ -367,20 +1486,25 @@ impl Board {
             .all(|influence| match influence {
                 Influence::Occupied(_) => true,
                 Influence::Ruled(_) => true,
-                Influence::Claimed(_) => false,
-                Influence::Tied => false,
+                Influence::Claimed(..) => false,
+                Influence::Tied(..) => false,
             })
     }
 
     fn one_player_has_unbeatable_lead(&self) -> bool {
-        let mut guaranteed_points = [0; MAX_PLAYERS];
+        let mut guaranteed_points = vec![0; self.num_teams()];
         let mut contested = 0;
         for influence in self.coords().map(|c| self.influence(c)) {
             match influence {
-                Influence::Occupied(player) => guaranteed_points[player as usize] += 1,
-                Influence::Ruled(player) => guaranteed_points[player as usize] += 1,
-                Influence::Claimed(_) => contested += 1,
-                Influence::Tied => contested += 1,
+                Influence::Occupied(player) => {
+                    guaranteed_points[self.teams.team_of(player) as usize] += 1
+                }
+                Influence::Ruled(team) if self.rules.count_claimed => {
+                    guaranteed_points[team as usize] += 1
+                }
+                Influence::Ruled(_) => contested += 1,
+                Influence::Claimed(..) => contested += 1,
+                Influence::Tied(..) => contested += 1,
             }
         }
 
@@ -398,93 +1522,953 @@ impl Board {
         most_points > second_most_points + contested
     }
 
-    pub fn is_game_over(&self, num_players: usize) -> bool {
-        if !self.more_than_one_player_has_valid_move(num_players) {
-            return true;
+    /// Why the game is over, or `None` if it isn't.
+    pub fn game_over_reason(&self) -> Option<GameOverReason> {
+        // The last team that can move already gets every cell they lead in,
+        // unless only occupied cells count, in which case they still have to fill them in:
+        let min_teams_with_moves = if self.rules.count_claimed { 2 } else { 1 };
+        if self.num_teams_with_valid_moves() < min_teams_with_moves {
+            return Some(GameOverReason::NoMoves);
+        }
+
+        if self.rules.ends_when_all_ruled() && self.everything_is_ruled_by_someone() {
+            return Some(GameOverReason::AllRuled);
+        }
+        if self.rules.end_on_unbeatable_lead && self.one_player_has_unbeatable_lead() {
+            return Some(GameOverReason::UnbeatableLead);
+        }
+
+        if self.rules.ends_when_settled() && !self.volatile_cells().contains(&true) {
+            Some(GameOverReason::Settled)
+        } else {
+            None
         }
+    }
 
-        // if self.everything_is_ruled_by_someone() {
-        //     return true;
-        // }
-        // if self.one_player_has_unbeatable_lead() {
-        //     return true;
-        // }
+    pub fn is_game_over(&self) -> bool {
+        self.game_over_reason().is_some()
+    }
 
-        let volatiles = self.volatile_cells(num_players);
-        !volatiles.iter().any(|is_volatile| *is_volatile)
+    /// Who won and why, or `None` if the game isn't over yet.
+    pub fn game_result(&self) -> Option<GameResult> {
+        let reason = self.game_over_reason()?;
+        Some(GameResult::new(reason, self.points()))
     }
 
     /// Given that the game is over, what are the scores?
     pub fn points(&self) -> Points {
-        let mut points = [0; MAX_PLAYERS];
-        for influence in self.coords().map(|c| self.influence(c)) {
-            if let Some(player) = influence.player() {
-                points[player as usize] += 1usize;
+        // Every cell is either occupied, or ruled or claimed by whoever leads there (if anyone).
+        // Unless the rules say only occupied cells count.
+        let counts = self.neighbor_counts();
+        let mut points = vec![0; self.num_teams()];
+        for (player, stones) in self.stones.iter().enumerate() {
+            points[self.teams.team_of(player as Player) as usize] += stones.count_ones();
+        }
+        if self.rules.count_claimed {
+            for (team, points) in points.iter_mut().enumerate() {
+                *points += self.leads(&counts, team as Team).count_ones();
             }
         }
         points
     }
+}
+
+// ----------------------------------------------------------------------------
+// ASCII diagrams:
+//
+//     A B C D
+//   1 . . X .
+//   2 . O . .
+//
+// One character per cell: `.` for empty, `#` for blocked, otherwise the player character.
+// The alternate form (`{:#}`) also marks free cells that are ruled or claimed
+// by someone with the lower case character of that player.
+
+/// One for each of the [`MAX_PLAYERS`].
+const PLAYER_CHARS: &[u8] = b"XOABCDEFGHIJKLMNPQRSTUVWYZ";
+
+/// The character used for this player in diagrams.
+pub fn player_char(player: Player) -> char {
+    PLAYER_CHARS[player as usize] as char
+}
+
+fn player_from_char(c: char) -> Option<Player> {
+    PLAYER_CHARS
+        .iter()
+        .position(|&pc| pc as char == c)
+        .map(|player| player as Player)
+}
+
+/// Layered boards are written one layer after the other, each headed by `Layer N`.
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for z in 0..self.depth {
+            if self.depth > 1 {
+                if z > 0 {
+                    write!(f, "\n\n")?;
+                }
+                writeln!(f, "Layer {}", layer_name(z))?;
+            }
+            self.fmt_layer(f, z)?;
+        }
+        Ok(())
+    }
+}
+
+impl Board {
+    fn fmt_layer(&self, f: &mut fmt::Formatter, z: i32) -> fmt::Result {
+        let row_label_width = row_name(self.height - 1).len();
+        let cell_width = column_name(self.width - 1).len();
+
+        write!(f, "{:width$}", "", width = row_label_width)?;
+        for x in 0..self.width {
+            write!(f, " {:>width$}", column_name(x), width = cell_width)?;
+        }
+
+        for y in 0..self.height {
+            write!(f, "\n{:>width$}", row_name(y), width = row_label_width)?;
+            for x in 0..self.width {
+                let c = Coord { x, y, z };
+                if !self.contains(c) {
+                    write!(f, " {:>width$}", '#', width = cell_width)?;
+                    continue;
+                }
+                let cell = match self.influence(c) {
+                    Influence::Occupied(player) => player_char(player),
+                    Influence::Ruled(team) | Influence::Claimed(team, _) if f.alternate() => {
+                        player_char(self.teams.leader(team)).to_ascii_lowercase()
+                    }
+                    _ => '.',
+                };
+                write!(f, " {:>width$}", cell, width = cell_width)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Failed to parse a [`Board`] diagram.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseBoardError {
+    /// Counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseBoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+/// A row of a diagram: its (1-based) line number, and its cells (`None` for blocked ones).
+type DiagramRow = (usize, Vec<Option<Cell>>);
+
+/// The rows of each layer of a diagram.
+fn parse_diagram_rows(text: &str) -> Result<Vec<Vec<DiagramRow>>, ParseBoardError> {
+    let mut layers: Vec<Vec<DiagramRow>> = vec![];
+    let mut rows: Vec<DiagramRow> = vec![];
+
+    let lines: Vec<&str> = text.lines().collect();
+    for (line_index, line) in lines.iter().enumerate() {
+        let line_nr = line_index + 1;
+        let error = |message: String| ParseBoardError {
+            line: line_nr,
+            message,
+        };
+
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        if words[0] == "Layer" {
+            if !rows.is_empty() {
+                layers.push(std::mem::take(&mut rows));
+            }
+            let expected = layer_name(layers.len() as i32);
+            if words[1..] != [expected.as_str()] {
+                return Err(error(format!("Expected 'Layer {}'", expected)));
+            }
+            continue;
+        }
+
+        // Without row labels, a first row like `A B` is marks, not column labels:
+        let next_row_is_labeled = lines[line_index + 1..]
+            .iter()
+            .find_map(|line| line.split_whitespace().next())
+            .is_some_and(|word| word.chars().all(|c| c.is_ascii_digit()));
+        let is_column_header = rows.is_empty()
+            && next_row_is_labeled
+            && words
+                .iter()
+                .enumerate()
+                .all(|(x, &word)| word == column_name(x as i32));
+        if is_column_header {
+            continue;
+        }
+
+        if words[0].chars().all(|c| c.is_ascii_digit()) {
+            words.remove(0); // row label
+        }
+
+        let mut row = vec![];
+        for word in words {
+            let mut chars = word.chars();
+            let c = match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => {
+                    return Err(error(format!(
+                        "Expected a single character, got '{}'",
+                        word
+                    )))
+                }
+            };
+            if c == '.' {
+                row.push(Some(None));
+            } else if c == '#' {
+                row.push(None);
+            } else if let Some(player) = player_from_char(c) {
+                row.push(Some(Some(player)));
+            } else if player_from_char(c.to_ascii_uppercase()).is_some() {
+                row.push(Some(None)); // Influence annotation
+            } else {
+                return Err(error(format!("Unknown cell '{}'", c)));
+            }
+        }
+
+        let first_row = layers.first().unwrap_or(&rows).first();
+        if let Some((_, first_row)) = first_row {
+            if row.len() != first_row.len() {
+                return Err(error(format!(
+                    "Expected {} cells, got {}",
+                    first_row.len(),
+                    row.len()
+                )));
+            }
+        }
+        rows.push((line_nr, row));
+    }
+
+    if rows.is_empty() {
+        return Err(ParseBoardError {
+            line: text.lines().count().max(1),
+            message: if layers.is_empty() {
+                "Empty board".to_owned()
+            } else {
+                "Empty layer".to_owned()
+            },
+        });
+    }
+    layers.push(rows);
+
+    for layer in &layers[1..] {
+        if layer.len() != layers[0].len() {
+            return Err(ParseBoardError {
+                line: layer.last().unwrap().0,
+                message: format!("Expected {} rows, got {}", layers[0].len(), layer.len()),
+            });
+        }
+    }
 
-    // pub fn leaders(&self) -> Vec<Player> {
-    //     let points = self.points();
-
-    //     let leader_score: usize = *points.iter().max().unwrap();
-    //     if leader_score == 0 {
-    //         vec![]
-    //     } else {
-    //         (0..MAX_PLAYERS)
-    //             .filter(|&player| points[player] == leader_score)
-    //             .map(|player| player as Player)
-    //             .collect()
-    //     }
-    // }
-
-    // pub fn leader(&self) -> Option<Player> {
-    //     let leaders = self.leaders();
-    //     if leaders.len() == 1 {
-    //         Some(leaders[0])
-    //     } else {
-    //         None
-    //     }
-    // }
-
-    // pub fn winner(&self, num_players: usize) -> Option<Player> {
-    //     if self.is_game_over(num_players) {
-    //         self.leader()
-    //     } else {
-    //         None
-    //     }
-    // }
+    Ok(layers)
 }
 
 impl Board {
-    pub fn ai_move(&self, player: Player, num_players: usize) -> Option<Coord> {
-        use rand::SeedableRng;
-        let mut rng = rand::rngs::SmallRng::from_entropy(); // Fast
+    /// Parse a diagram in the same format as `Display` writes.
+    /// The row and column labels are optional, but column labels need row labels below them.
+    pub fn from_diagram(text: &str, num_players: usize) -> Result<Board, ParseBoardError> {
+        let layers = parse_diagram_rows(text)?;
+        let width = layers[0][0].1.len() as i32;
+        let height = layers[0].len() as i32;
+        let depth = layers.len() as i32;
+
+        // All layers share the same map.
+        let mut map = Map::full(width, height);
+        for (y, (_, row)) in layers[0].iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                if cell.is_none() {
+                    map.block(Coord::new(x as i32, y as i32));
+                }
+            }
+        }
+        for layer in &layers[1..] {
+            for (y, (line_nr, row)) in layer.iter().enumerate() {
+                for (x, cell) in row.iter().enumerate() {
+                    if cell.is_none() == map.contains(Coord::new(x as i32, y as i32)) {
+                        return Err(ParseBoardError {
+                            line: *line_nr,
+                            message: "All layers must have the same blocked cells".to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut board = Board::from_map(&map, num_players).with_layers(depth);
+        for (z, rows) in layers.into_iter().enumerate() {
+            for (y, (line_nr, row)) in rows.into_iter().enumerate() {
+                for (x, cell) in row.into_iter().enumerate() {
+                    if let Some(Some(player)) = cell {
+                        if player as usize >= num_players {
+                            return Err(ParseBoardError {
+                                line: line_nr,
+                                message: format!(
+                                    "'{}' is player {}, but there are only {} players",
+                                    player_char(player),
+                                    player,
+                                    num_players
+                                ),
+                            });
+                        }
+                        let c = Coord {
+                            x: x as i32,
+                            y: y as i32,
+                            z: z as i32,
+                        };
+                        board.set(c, player);
+                    }
+                }
+            }
+        }
+        Ok(board)
+    }
+}
+
+impl std::str::FromStr for Board {
+    type Err = ParseBoardError;
+
+    /// Parse a diagram, assuming the players in it are all there is (but at least two).
+    fn from_str(text: &str) -> Result<Board, ParseBoardError> {
+        let layers = parse_diagram_rows(text)?;
+        let max_player = layers
+            .iter()
+            .flatten()
+            .flat_map(|(_, row)| row)
+            .flatten()
+            .flatten()
+            .max();
+        let num_players = max_player.map_or(2, |&player| (player as usize + 1).max(2));
+        Board::from_diagram(text, num_players)
+    }
+}
 
-        let state = mcts::GameState {
+impl Board {
+    /// The board as seen by the AI, with `player` to move.
+    fn ai_state(&self, player: Player) -> mcts::GameState {
+        mcts::GameState {
             next_player: player,
-            num_players,
             board: self.clone(),
+            // We don't know the history, so assume one action per mark:
+            num_actions: self.occupied().count_ones(),
+            swappable: None,
+        }
+    }
+
+    /// Let the AI pick a move for `player`, or `None` if they should pass.
+    /// See [`crate::game::Game::ai_action`] if you know how the game got here.
+    pub fn ai_move(&self, player: Player) -> Option<Coord> {
+        match ai::ai_action(self.ai_state(player)) {
+            Some(mcts::Action::Move(coord)) => Some(coord),
+            _ => None,
+        }
+    }
+
+    /// Like [`Board::ai_move`], but reproducible: the same board, seed and number of iterations
+    /// give the same move on every platform.
+    pub fn ai_move_seeded(&self, player: Player, seed: u64, iterations: usize) -> Option<Coord> {
+        match ai::ai_action_seeded(self.ai_state(player), seed, iterations) {
+            Some(mcts::Action::Move(coord)) => Some(coord),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::ai::AiRng;
+    use crate::topology::Neighborhood;
+
+    /// Marks for random players in random cells, without regard for the rules.
+    fn scatter(board: &mut Board, rng: &mut AiRng, num_marks: usize) {
+        let coords: Vec<Coord> = board.coords().collect();
+        for _ in 0..num_marks {
+            let c = coords[rng.gen_range(0, coords.len())];
+            let player = rng.gen_range(0, board.num_players()) as Player;
+            board.set(c, player);
+        }
+    }
+
+    /// Rules with a random claim margin and tie rule (and game end, which shouldn't matter).
+    fn random_rules(rng: &mut AiRng) -> RuleSet {
+        RuleSet {
+            claim_margin: rng.gen_range(1, 4),
+            ties_allow_placement: rng.gen(),
+            end_when_settled: rng.gen(),
+            end_when_all_ruled: rng.gen(),
+            end_on_unbeatable_lead: rng.gen(),
+            count_claimed: rng.gen(),
+            swap: false,
+        }
+    }
+
+    /// What [`Board::valid_moves`] should be, worked out one cell at a time straight from the rules:
+    /// free cells where no enemy team outnumbers `player`:s team by the claim margin,
+    /// nor (if ties block) has as many neighbors there as them.
+    fn naive_valid_moves(board: &Board, player: Player) -> Vec<Coord> {
+        let rules = board.rules();
+        let team = board.teams().team_of(player) as usize;
+        board
+            .coords()
+            .filter(|&c| {
+                let (influences, _) = board.count_neighbors_of(c);
+                let friends = influences[team] as u32;
+                board.at(c).is_none()
+                    && (0..board.num_teams()).filter(|&t| t != team).all(|enemy| {
+                        let enemies = influences[enemy] as u32;
+                        enemies < friends + rules.claim_margin as u32
+                            && (rules.ties_allow_placement || friends == 0 || enemies != friends)
+                    })
+            })
+            .collect()
+    }
+
+    fn assert_valid_moves_match_check_move(board: &Board) {
+        for player in 0..board.num_players() as Player {
+            let expected = naive_valid_moves(board, player);
+            let mut moves = board.valid_moves(player);
+            moves.sort_by_key(|c| (c.z, c.y, c.x));
+            let checked: Vec<Coord> = board
+                .coords()
+                .filter(|&c| board.check_move(c, player).is_ok())
+                .collect();
+            assert_eq!(
+                (&moves, &checked),
+                (&expected, &expected),
+                "Player {} with {} on\n{}",
+                player,
+                board.rules(),
+                board
+            );
+        }
+    }
+
+    #[test]
+    fn valid_moves_match_check_move() {
+        let mut rng = AiRng::seed_from_u64(0);
+        for _ in 0..300 {
+            let (width, height) = (rng.gen_range(1, 10), rng.gen_range(1, 10));
+            let num_players = rng.gen_range(2, 5);
+            let mut board =
+                Board::new(width, height, num_players).with_rules(random_rules(&mut rng));
+            if num_players == 4 && rng.gen() {
+                board = board.with_teams(Teams::round_robin(4, 2));
+            }
+            let num_marks = rng.gen_range(0, (width * height) as usize + 1);
+            scatter(&mut board, &mut rng, num_marks);
+            assert_valid_moves_match_check_move(&board);
+        }
+    }
+
+    #[test]
+    fn ties_block_against_any_enemy() {
+        let rules = RuleSet {
+            claim_margin: 3,
+            ties_allow_placement: false,
+            ..RuleSet::default()
         };
+        let board = Board::from_diagram("A . O\nX A A", 4)
+            .unwrap()
+            .with_rules(rules);
+        // A has the most neighbors around B1, but not enough to claim it, and O ties with X:
+        let b1 = "B1".parse().unwrap();
+        assert_eq!(
+            board.check_move(b1, 0),
+            Err(HobogoError::Tied {
+                coord: b1,
+                enemy: 1,
+                neighbors: 1
+            })
+        );
+        assert!(board.valid_moves(0).is_empty());
+        assert_eq!(board.valid_moves(2), vec![b1]);
+    }
+
+    #[test]
+    fn valid_moves_match_check_move_on_small_tori() {
+        let mut rng = AiRng::seed_from_u64(2);
+        for &neighborhood in &[
+            Neighborhood::Moore,
+            Neighborhood::VonNeumann,
+            Neighborhood::Hex,
+        ] {
+            let topology = Topology {
+                neighborhood,
+                wrap: true,
+            };
+            for width in 1..5 {
+                for height in 1..5 {
+                    for depth in 1..3 {
+                        for _ in 0..10 {
+                            let mut board = Board::new(width, height, 3)
+                                .with_rules(random_rules(&mut rng))
+                                .with_topology(topology)
+                                .with_layers(depth);
+                            let num_marks = rng.gen_range(0, board.coords().count() + 1);
+                            scatter(&mut board, &mut rng, num_marks);
+                            assert_valid_moves_match_check_move(&board);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapped_neighbors_count_once() {
+        let topology = Topology {
+            neighborhood: Neighborhood::VonNeumann,
+            wrap: true,
+        };
+        let board = Board::new(2, 3, 2).with_topology(topology);
+        // Left and right are the same cell, up and down are not:
+        let neighbors: Vec<Coord> = board.neighbors_to(Coord::new(1, 1)).collect();
+        assert_eq!(
+            neighbors,
+            vec![
+                Coord::new(1, 1),
+                Coord::new(1, 0),
+                Coord::new(0, 1),
+                Coord::new(1, 2)
+            ]
+        );
+        let board = Board::new(1, 1, 2).with_topology(topology);
+        assert_eq!(board.neighbors_to(Coord::new(0, 0)).count(), 1);
+    }
+
+    #[test]
+    fn diagram_round_trip() {
+        let mut rng = AiRng::seed_from_u64(4);
+        let mut map = Map::full(28, 11);
+        map.block(Coord::new(27, 10));
+        for &depth in &[1, 2] {
+            let mut board = Board::from_map(&map, 3).with_layers(depth);
+            scatter(&mut board, &mut rng, 50);
+            let text = board.to_string();
+            assert!(text.parse::<Board>().unwrap() == board, "{}", text);
+            // The influence annotations are skipped:
+            assert!(format!("{:#}", board).parse::<Board>().unwrap() == board);
+        }
+    }
+
+    #[test]
+    fn column_labels_need_row_labels() {
+        let labeled: Board = "  A B\n1 X .\n2 . O".parse().unwrap();
+        assert_eq!((labeled.width, labeled.height), (2, 2));
+        assert_eq!(labeled.at(Coord::new(1, 1)), Some(1));
+
+        // Players A and B:
+        let unlabeled: Board = "A B\n. .\n. .".parse().unwrap();
+        assert_eq!((unlabeled.width, unlabeled.height), (2, 3));
+        assert_eq!(unlabeled.num_players(), 4);
+        assert_eq!(unlabeled.at(Coord::new(0, 0)), Some(2));
+        assert_eq!(unlabeled.at(Coord::new(1, 0)), Some(3));
+    }
 
-        let think_time = 1.0;
-        let mut mcts = mcts::Mcts::new(state);
-        let start = now_sec();
-        while {
-            mcts.iterate(&mut rng);
-            now_sec() - start < think_time
-        } {}
+    #[test]
+    fn player_sets() {
+        let last = MAX_PLAYERS as Player - 1;
+        let mut set: PlayerSet = [3, 0, last].iter().copied().collect();
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![0, 3, last]);
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(last) && !set.contains(1) && !set.contains(last + 1));
+        set.insert(1);
+        assert_eq!(set.len(), 4);
+        assert!(!set.is_empty() && PlayerSet::default().is_empty());
+    }
+
+    #[test]
+    fn diagrams_have_a_letter_for_every_player() {
+        assert!(is_valid_num_players(MAX_PLAYERS));
+        assert!(!is_valid_num_players(MAX_PLAYERS + 1));
+        let mut board = Board::new(MAX_PLAYERS as i32, 2, MAX_PLAYERS);
+        for player in 0..MAX_PLAYERS {
+            board.set(
+                Coord::new(player as i32, player as i32 % 2),
+                player as Player,
+            );
+        }
+        let parsed = Board::from_diagram(&board.to_string(), MAX_PLAYERS).unwrap();
+        assert!(parsed == board, "{}", board);
 
-        let action = mcts.best_action().cloned();
+        let too_many: Vec<Team> = (0..=MAX_PLAYERS)
+            .map(|player| (player % 2) as Team)
+            .collect();
+        assert!(Teams::try_from(too_many).is_err());
+    }
 
-        if let Some(action) = action {
-            match action {
-                mcts::Action::Pass => None,
-                mcts::Action::Move(coord) => Some(coord),
+    #[test]
+    fn deserialize_checks_the_board() {
+        let board: Board = "X . .\n. O .".parse().unwrap();
+        let json = serde_json::to_value(&board).unwrap();
+        let loaded: Board = serde_json::from_value(json.clone()).unwrap();
+        assert!(loaded == board);
+
+        let error = |change: &dyn Fn(&mut serde_json::Value)| {
+            let mut json = json.clone();
+            change(&mut json);
+            match serde_json::from_value::<Board>(json) {
+                Ok(board) => panic!("Loaded{:?}", board),
+                Err(err) => err.to_string(),
             }
-        } else {
-            None
+        };
+        assert_eq!(
+            error(&|json| json["cells"].as_array_mut().unwrap().truncate(5)),
+            "Invalid board: 5 cells for a 3x2x1 board"
+        );
+        assert_eq!(
+            error(&|json| json["height"] = 3.into()),
+            "Invalid board: 6 cells for a 3x3x1 board"
+        );
+        assert_eq!(
+            error(&|json| json["width"] = 0.into()),
+            "Invalid board: bad size 0x2"
+        );
+        assert_eq!(
+            error(&|json| json["cells"][2] = 2.into()),
+            "Invalid board: cell C1: There is no player 2"
+        );
+        assert_eq!(
+            error(&|json| json["num_players"] = 1.into()),
+            "Invalid board: bad number of players: 1"
+        );
+        assert_eq!(
+            error(&|json| json["teams"] = serde_json::json!([0, 1, 0])),
+            "Invalid board: teams for 3 players in a 2 player game"
+        );
+        assert_eq!(
+            error(&|json| json["map"] = serde_json::to_value(Map::full(2, 2)).unwrap()),
+            "Invalid board: 2x2 map for a 3x2 board"
+        );
+    }
+
+    #[test]
+    fn column_names() {
+        let names: Vec<String> = [0, 1, 25, 26, 27, 51, 52, 701, 702]
+            .iter()
+            .map(|&x| column_name(x))
+            .collect();
+        assert_eq!(names, ["A", "B", "Z", "AA", "AB", "AZ", "BA", "ZZ", "AAA"]);
+    }
+
+    #[test]
+    fn coords_and_actions_from_text() {
+        for x in 0..1000 {
+            for &(y, z) in &[(0, 0), (9, 0), (99, 2)] {
+                let c = Coord { x, y, z };
+                assert_eq!(c.to_string().parse(), Ok(c));
+                assert_eq!(
+                    c.to_string().to_lowercase().parse(),
+                    Ok(mcts::Action::Move(c))
+                );
+            }
+        }
+        assert_eq!("aa12".parse(), Ok(Coord::new(26, 11)));
+        assert_eq!("B2/3".parse(), Ok(Coord { x: 1, y: 1, z: 2 }));
+        for bad in &[
+            "",
+            "C",
+            "4",
+            "4C",
+            "C0",
+            "C-1",
+            "C4/",
+            "C4/0",
+            "C4/x",
+            "C 4",
+            "Ö4",
+            "ZZZZZZZZZZ1",
+        ] {
+            assert_eq!(bad.parse::<Coord>(), Err(ParseCoordError(bad.to_string())));
+        }
+
+        for &action in &[mcts::Action::Pass, mcts::Action::Swap] {
+            assert_eq!(action.to_string().parse(), Ok(action));
+            assert_eq!(action.to_string().to_lowercase().parse(), Ok(action));
+        }
+        assert!("passes".parse::<mcts::Action>().is_err());
+    }
+
+    #[test]
+    fn placements() {
+        let result = GameResult::new(GameOverReason::NoMoves, vec![3, 5, 3, 1]);
+        assert_eq!(result.placements, vec![vec![1], vec![0, 2], vec![3]]);
+        assert_eq!(result.winner(), Some(1));
+        assert!(!result.is_tie());
+        assert_eq!(
+            (0..4)
+                .map(|team| result.placement_of(team))
+                .collect::<Vec<_>>(),
+            vec![1, 0, 1, 2]
+        );
+
+        let result = GameResult::new(GameOverReason::Settled, vec![4, 2, 4]);
+        assert_eq!(result.winners(), &[0, 2]);
+        assert_eq!(result.winner(), None);
+        assert!(result.is_tie());
+    }
+
+    #[test]
+    fn game_results() {
+        let board: Board = "X . .\n. . .\n. . O".parse().unwrap();
+        assert_eq!(board.game_result(), None);
+
+        // O can't move anywhere, and X gets the free cells they lead in:
+        let board: Board = "X X .\nX X .\n. . O".parse().unwrap();
+        let result = board.game_result().unwrap();
+        assert_eq!(result.reason, GameOverReason::NoMoves);
+        assert_eq!(result.points, vec![8, 1]);
+        assert_eq!(result.winner(), Some(0));
+    }
+
+    /// The influence as `(kind, teams, count)`, to compare it.
+    fn influence_at(board: &Board, c: &str) -> (&'static str, Vec<Team>, u8) {
+        match board.influence(c.parse().unwrap()) {
+            Influence::Occupied(player) => ("occupied", vec![player], 0),
+            Influence::Ruled(team) => ("ruled", vec![team], 0),
+            Influence::Claimed(team, lead) => ("claimed", vec![team], lead),
+            Influence::Tied(teams, count) => ("tied", teams.iter().collect(), count),
+        }
+    }
+
+    #[test]
+    fn influence_reports_everyone_tied() {
+        let board = Board::new(3, 2, 3);
+        assert_eq!(influence_at(&board, "B2"), ("tied", vec![0, 1, 2], 0));
+
+        let board: Board = "X O A\n. . .".parse().unwrap();
+        assert_eq!(influence_at(&board, "A2"), ("tied", vec![0, 1], 1));
+        assert_eq!(influence_at(&board, "B2"), ("tied", vec![0, 1, 2], 1));
+        assert_eq!(influence_at(&board, "C2"), ("tied", vec![1, 2], 1));
+        assert_eq!(influence_at(&board, "C1"), ("occupied", vec![2], 0));
+    }
+
+    #[test]
+    fn influence_reports_the_lead() {
+        let board: Board = "X X O\n. . .".parse().unwrap();
+        assert_eq!(influence_at(&board, "A2"), ("claimed", vec![0], 2));
+        assert_eq!(influence_at(&board, "B2"), ("claimed", vec![0], 1));
+        assert_eq!(influence_at(&board, "C2"), ("tied", vec![0, 1], 1));
+
+        // Leading by one is not enough with a claim margin of two:
+        let margin_2 = RuleSet {
+            claim_margin: 2,
+            ..RuleSet::default()
+        };
+        let board = board.with_rules(margin_2);
+        assert_eq!(influence_at(&board, "A2"), ("claimed", vec![0], 2));
+        assert_eq!(influence_at(&board, "B2"), ("tied", vec![0], 2));
+
+        // Nobody else can ever get as many neighbors around A1:
+        let board: Board = ". X\nX X".parse().unwrap();
+        assert_eq!(influence_at(&board, "A1"), ("ruled", vec![0], 0));
+    }
+
+    #[test]
+    fn stones_only_games_are_played_out() {
+        let settled: Board = "X . # . O".parse().unwrap();
+        let result = settled.game_result().unwrap();
+        assert_eq!(result.reason, GameOverReason::Settled);
+        assert_eq!(result.points, vec![2, 2]);
+
+        // The free cells aren't worth anything until someone moves there:
+        for &end_when_all_ruled in &[false, true] {
+            let rules = RuleSet {
+                count_claimed: false,
+                end_when_all_ruled,
+                ..RuleSet::default()
+            };
+            let mut board = settled.clone().with_rules(rules);
+            assert_eq!(board.game_result(), None);
+            board.try_play("B1".parse().unwrap(), 0).unwrap();
+            assert_eq!(board.game_result(), None);
+            board.try_play("D1".parse().unwrap(), 1).unwrap();
+            let result = board.game_result().unwrap();
+            assert_eq!(result.reason, GameOverReason::NoMoves);
+            assert_eq!(result.points, vec![2, 2]);
+        }
+    }
+
+    #[test]
+    fn symmetries_can_be_undone() {
+        let (width, height) = (3, 5);
+        for &symmetry in &Symmetry::ALL {
+            let (new_width, new_height) = if symmetry.swaps_axes() {
+                (height, width)
+            } else {
+                (width, height)
+            };
+            for c in rect_coords(width, height) {
+                let moved = symmetry.transform(c, width, height);
+                assert!(
+                    0 <= moved.x && moved.x < new_width && 0 <= moved.y && moved.y < new_height
+                );
+                assert_eq!(
+                    symmetry.inverse().transform(moved, new_width, new_height),
+                    c,
+                    "{:?}",
+                    symmetry
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_boards_have_the_same_canonical_form() {
+        let hex = Topology {
+            neighborhood: Neighborhood::Hex,
+            wrap: false,
+        };
+        let mut corner_blocked = Map::full(4, 4);
+        corner_blocked.block(Coord::new(0, 0));
+        let boards = [
+            (Board::new(4, 4, 3), 8),
+            (Board::new(3, 5, 3), 4),
+            (Board::new(4, 4, 3).with_topology(hex), 4),
+            (Board::from_map(&corner_blocked, 3), 2),
+        ];
+
+        let mut rng = AiRng::seed_from_u64(3);
+        for (board, num_symmetries) in boards.iter() {
+            for _ in 0..10 {
+                let mut board = board.clone();
+                scatter(&mut board, &mut rng, 5);
+                let (canonical, symmetry) = board.canonical();
+                assert!(board.transformed(symmetry) == canonical);
+                let symmetries: Vec<Symmetry> = board.symmetries().collect();
+                assert_eq!(symmetries.len(), *num_symmetries, "{:?}", symmetries);
+                for symmetry in symmetries {
+                    let moved = board.transformed(symmetry);
+                    assert!(
+                        moved.canonical().0 == canonical,
+                        "{:?} of{:?}",
+                        symmetry,
+                        board
+                    );
+                    // Actions move along with the board:
+                    for c in board.coords() {
+                        let action =
+                            mcts::Action::Move(c).transformed(symmetry, board.width, board.height);
+                        match action {
+                            mcts::Action::Move(to) => assert_eq!(moved.at(to), board.at(c)),
+                            _ => panic!("{:?} is no move", action),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Random games on boards of all shapes, with some marks taken back along the way.
+    /// Calls `check` after every change.
+    fn play_random_games(mut check: impl FnMut(&Board)) {
+        let mut rng = AiRng::seed_from_u64(1);
+        let neighborhoods = [
+            Neighborhood::Moore,
+            Neighborhood::VonNeumann,
+            Neighborhood::Hex,
+        ];
+        for _ in 0..100 {
+            let (width, height) = (rng.gen_range(1, 8), rng.gen_range(1, 8));
+            let num_players = rng.gen_range(2, 5);
+            let topology = Topology {
+                neighborhood: neighborhoods[rng.gen_range(0, neighborhoods.len())],
+                wrap: rng.gen(),
+            };
+            let mut board = Board::new(width, height, num_players)
+                .with_topology(topology)
+                .with_layers(rng.gen_range(1, 3));
+            if num_players == 4 && rng.gen() {
+                board = board.with_teams(Teams::round_robin(4, 2));
+            }
+            check(&board);
+            let mut player = 0;
+            for _ in 0..board.coords().count() {
+                if let Some(c) = board.random_valid_move(&mut rng, player) {
+                    board.set(c, player);
+                    check(&board);
+                }
+                if rng.gen_range(0, 5) == 0 {
+                    let coords: Vec<Coord> = board.coords().collect();
+                    board.clear(coords[rng.gen_range(0, coords.len())]);
+                    check(&board);
+                }
+                player = (player + 1) % num_players as Player;
+            }
+        }
+    }
+
+    #[test]
+    fn tallies_match_recount() {
+        play_random_games(|board| {
+            assert!(board.tallies_are_consistent(), "On\n{}", board);
+        });
+    }
+
+    #[test]
+    fn zobrist_matches_recompute() {
+        play_random_games(|board| {
+            let recomputed = board
+                .coords()
+                .filter_map(|c| Some(zobrist_cell_key(board.index(c)?, board.at(c)?)))
+                .fold(0, |hash, key| hash ^ key);
+            assert_eq!(board.zobrist(), recomputed, "On\n{}", board);
+        });
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture playout_speed`.
+    #[test]
+    #[ignore]
+    fn playout_speed() {
+        fn playouts_per_second(moves: impl Fn(&Board, Player) -> Vec<Coord>) -> f64 {
+            let mut rng = AiRng::seed_from_u64(0);
+            let start = std::time::Instant::now();
+            let mut num_playouts = 0;
+            while start.elapsed().as_secs_f64() < 2.0 {
+                let mut board = Board::new(9, 9, 2);
+                let mut player = 0;
+                let mut num_passes = 0;
+                while num_passes < 2 {
+                    let moves = moves(&board, player);
+                    if moves.is_empty() {
+                        num_passes += 1;
+                    } else {
+                        num_passes = 0;
+                        board.set(moves[rng.gen_range(0, moves.len())], player);
+                    }
+                    player = 1 - player;
+                }
+                num_playouts += 1;
+            }
+            num_playouts as f64 / start.elapsed().as_secs_f64()
         }
+        let bitboard = playouts_per_second(Board::valid_moves);
+        let per_cell = playouts_per_second(naive_valid_moves);
+        println!(
+            "9x9 random playouts per second: {:.0} with bitboards, {:.0} checking each cell ({:.1}x)",
+            bitboard,
+            per_cell,
+            bitboard / per_cell
+        );
     }
 }
@@ -1,9 +1,23 @@
 #![deny(warnings)]
 #![allow(dead_code)] // TODO
 
+mod ai;
 mod app;
+mod bitboard;
+mod game;
 mod hobogo;
+mod map;
 mod mcts;
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+mod parallel;
+mod record;
+mod rules;
+mod setup;
+mod solver;
+mod teams;
+#[cfg(test)]
+mod tictactoe;
+mod topology;
 
 // ----------------------------------------------------------------------------
 
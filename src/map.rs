@@ -0,0 +1,338 @@
+//! The shape of a board: which cells of its rectangle can be played.
+//!
+//! Maps are written as rows of `.` (a cell) and `#` (blocked):
+//!
+//! ```text
+//! # . . . #
+//! . . . . .
+//! . . # . .
+//! . . . . .
+//! # . . . #
+//! ```
+//!
+//! Whitespace between the cells is optional, and rows may also be separated by `/`,
+//! so the same map can be written on one line as `#...#/...../..#../...../#...#`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hobogo::{Coord, Symmetry};
+
+/// Which cells of a `width` x `height` rectangle are part of the board.
+///
+/// Blocked cells are neither playable nor neighbors of anything.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "MapData")]
+pub struct Map {
+    width: i32,
+    height: i32,
+    /// Row by row. `false` for blocked cells.
+    cells: Vec<bool>,
+}
+
+impl Map {
+    /// The whole rectangle.
+    pub fn full(width: i32, height: i32) -> Map {
+        Map {
+            width,
+            height,
+            cells: vec![true; (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Is this a playable cell?
+    pub fn contains(&self, c: Coord) -> bool {
+        0 <= c.x
+            && c.x < self.width
+            && 0 <= c.y
+            && c.y < self.height
+            && self.cells[(self.width * c.y + c.x) as usize]
+    }
+
+    /// Make a cell unplayable.
+    pub fn block(&mut self, c: Coord) {
+        assert!(
+            0 <= c.x && c.x < self.width && 0 <= c.y && c.y < self.height,
+            "{} is outside the map",
+            c
+        );
+        self.cells[(self.width * c.y + c.x) as usize] = false;
+    }
+
+    /// No blocked cells?
+    pub fn is_full(&self) -> bool {
+        self.cells.iter().all(|&cell| cell)
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.cells.iter().filter(|&&cell| cell).count()
+    }
+
+    /// The map rotated or reflected.
+    pub fn transformed(&self, symmetry: Symmetry) -> Map {
+        let (width, height) = if symmetry.swaps_axes() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        let mut map = Map::full(width, height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = Coord::new(x, y);
+                if !self.contains(c) {
+                    map.block(symmetry.transform(c, self.width, self.height));
+                }
+            }
+        }
+        map
+    }
+
+    /// The map on one line, with rows separated by `/`.
+    pub fn to_line(&self) -> String {
+        self.to_string()
+            .lines()
+            .map(|row| row.replace(' ', ""))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in 0..self.height {
+            if y > 0 {
+                writeln!(f)?;
+            }
+            let row: Vec<&str> = (0..self.width)
+                .map(|x| {
+                    if self.contains(Coord::new(x, y)) {
+                        "."
+                    } else {
+                        "#"
+                    }
+                })
+                .collect();
+            f.write_str(&row.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a [`Map`] could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseMapError(String);
+
+impl fmt::Display for ParseMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bad map: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseMapError {}
+
+/// The serialized form of a [`Map`], before it has been checked.
+#[derive(Deserialize)]
+struct MapData {
+    width: i32,
+    height: i32,
+    cells: Vec<bool>,
+}
+
+impl TryFrom<MapData> for Map {
+    type Error = ParseMapError;
+
+    fn try_from(data: MapData) -> Result<Map, ParseMapError> {
+        if data.width < 1 || data.height < 1 {
+            return Err(ParseMapError(format!(
+                "bad size {}x{}",
+                data.width, data.height
+            )));
+        }
+        if data.cells.len() as u64 != data.width as u64 * data.height as u64 {
+            return Err(ParseMapError(format!(
+                "{} cells for a {}x{} map",
+                data.cells.len(),
+                data.width,
+                data.height
+            )));
+        }
+        let map = Map {
+            width: data.width,
+            height: data.height,
+            cells: data.cells,
+        };
+        if map.num_cells() == 0 {
+            return Err(ParseMapError("no cells".to_owned()));
+        }
+        Ok(map)
+    }
+}
+
+impl FromStr for Map {
+    type Err = ParseMapError;
+
+    fn from_str(text: &str) -> Result<Map, ParseMapError> {
+        let mut rows: Vec<Vec<bool>> = vec![];
+        for line in text.lines().flat_map(|line| line.split('/')) {
+            let mut row = vec![];
+            for c in line.chars().filter(|c| !c.is_whitespace()) {
+                match c {
+                    '.' => row.push(true),
+                    '#' => row.push(false),
+                    _ => return Err(ParseMapError(format!("unknown cell '{}'", c))),
+                }
+            }
+            if row.is_empty() {
+                continue;
+            }
+            if let Some(first_row) = rows.first() {
+                if row.len() != first_row.len() {
+                    return Err(ParseMapError(format!(
+                        "row {} has {} cells, but the first row has {}",
+                        rows.len() + 1,
+                        row.len(),
+                        first_row.len()
+                    )));
+                }
+            }
+            rows.push(row);
+        }
+
+        let map = Map {
+            width: rows.first().map_or(0, |row| row.len() as i32),
+            height: rows.len() as i32,
+            cells: rows.concat(),
+        };
+        if map.num_cells() == 0 {
+            return Err(ParseMapError("no cells".to_owned()));
+        }
+        Ok(map)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Maps that come with the game, by name.
+pub const BUNDLED_MAPS: &[(&str, &str)] = &[
+    (
+        "Plus",
+        "
+        # # # . . . # # #
+        # # # . . . # # #
+        # # # . . . # # #
+        . . . . . . . . .
+        . . . . . . . . .
+        . . . . . . . . .
+        # # # . . . # # #
+        # # # . . . # # #
+        # # # . . . # # #
+        ",
+    ),
+    (
+        "Diamond",
+        "
+        # # # # . # # # #
+        # # # . . . # # #
+        # # . . . . . # #
+        # . . . . . . . #
+        . . . . . . . . .
+        # . . . . . . . #
+        # # . . . . . # #
+        # # # . . . # # #
+        # # # # . # # # #
+        ",
+    ),
+    (
+        "Donut",
+        "
+        . . . . . . . . .
+        . . . . . . . . .
+        . . . . . . . . .
+        . . . # # # . . .
+        . . . # # # . . .
+        . . . # # # . . .
+        . . . . . . . . .
+        . . . . . . . . .
+        . . . . . . . . .
+        ",
+    ),
+    (
+        "Islands",
+        "
+        . . . . # . . . .
+        . . . . # . . . .
+        . . . . . . . . .
+        . . . . # . . . .
+        # # . # # # . # #
+        . . . . # . . . .
+        . . . . . . . . .
+        . . . . # . . . .
+        . . . . # . . . .
+        ",
+    ),
+];
+
+/// One of the [`BUNDLED_MAPS`].
+pub fn bundled_map(name: &str) -> Option<Map> {
+    BUNDLED_MAPS
+        .iter()
+        .find(|(map_name, _)| *map_name == name)
+        .map(|(_, text)| text.parse().expect("Bad bundled map"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_checks_the_size() {
+        let map: Map = "#.#/.../#.#".parse().unwrap();
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(serde_json::from_str::<Map>(&json).unwrap(), map);
+
+        let load = |json: &str| {
+            serde_json::from_str::<Map>(json)
+                .err()
+                .map(|err| err.to_string())
+        };
+        assert_eq!(
+            load(r#"{"width": 3, "height": 3, "cells": [true, true]}"#),
+            Some("Bad map: 2 cells for a 3x3 map".to_owned())
+        );
+        assert_eq!(
+            load(r#"{"width": -1, "height": 3, "cells": []}"#),
+            Some("Bad map: bad size -1x3".to_owned())
+        );
+        assert_eq!(
+            load(r#"{"width": 1, "height": 1, "cells": [false]}"#),
+            Some("Bad map: no cells".to_owned())
+        );
+    }
+
+    #[test]
+    fn board_with_a_short_map_is_an_error() {
+        let board = crate::hobogo::Board::from_map(&"#.#/.../#.#".parse().unwrap(), 2);
+        let mut json: serde_json::Value = serde_json::to_value(&board).unwrap();
+        json["map"]["cells"].as_array_mut().unwrap().truncate(4);
+        assert!(serde_json::from_value::<crate::hobogo::Board>(json).is_err());
+    }
+
+    #[test]
+    fn text_round_trip() {
+        for (name, text) in BUNDLED_MAPS {
+            let map: Map = text.parse().unwrap();
+            assert_eq!(map.to_line().parse::<Map>().as_ref(), Ok(&map), "{}", name);
+        }
+    }
+}
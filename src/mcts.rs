@@ -1,17 +1,74 @@
-use std::collections::VecDeque;
 use std::fmt;
 
-use rand::{seq::SliceRandom, Rng};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::hobogo::{Board, Coord, Influence, Player};
+use crate::hobogo::{zobrist_turn_key, Board, Coord, ParseCoordError, Player, Symmetry};
 
 // ----------------------------------------------------------------------------
 
-// TODO: traitify
-#[derive(Clone, Copy, Debug)]
+/// Score for each player. Higher is better.
+pub type Score = Vec<f64>;
+
+/// A turn-based game that [`Mcts`] can search.
+pub trait Game: Clone {
+    type Action: Copy + PartialEq + fmt::Display;
+
+    fn num_players(&self) -> usize;
+
+    /// Whose turn it is, counting from 0.
+    fn next_player(&self) -> usize;
+
+    /// Everything the next player can do. Empty when the game is over.
+    fn available_actions(&self) -> Vec<Self::Action>;
+
+    /// Let the next player take the action, and move on to the player after them.
+    fn take_action(&mut self, action: &Self::Action);
+
+    fn is_game_over(&self) -> bool {
+        self.available_actions().is_empty()
+    }
+
+    /// Only called when the game is over.
+    fn score(&self) -> Score;
+
+    /// An action for the random playouts, or `None` if the game is over.
+    /// Override this with something faster or smarter than picking among all available actions.
+    fn random_action<R: Rng>(&self, rng: &mut R) -> Option<Self::Action> {
+        use rand::seq::SliceRandom;
+        self.available_actions().choose(rng).copied()
+    }
+
+    /// Play random actions until the game is over.
+    fn random_playout<R: Rng>(&mut self, rng: &mut R) {
+        while let Some(action) = self.random_action(rng) {
+            self.take_action(&action)
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Hobogo:
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum Action {
     Pass,
     Move(Coord),
+    /// Take over the first mark of the game instead of making the second move.
+    /// Only when the rules allow it (see [`crate::rules::RuleSet::swap`]).
+    Swap,
+}
+
+impl Action {
+    /// Move the action with the board it is played on,
+    /// a board of the given size (before the transform).
+    pub fn transformed(self, symmetry: Symmetry, width: i32, height: i32) -> Action {
+        match self {
+            Action::Pass => Action::Pass,
+            Action::Move(coord) => Action::Move(symmetry.transform(coord, width, height)),
+            Action::Swap => Action::Swap,
+        }
+    }
 }
 
 impl fmt::Display for Action {
@@ -19,107 +76,151 @@ impl fmt::Display for Action {
         match self {
             Action::Pass => f.write_str("PASS"),
             Action::Move(coord) => coord.fmt(f),
+            Action::Swap => f.write_str("SWAP"),
         }
     }
 }
 
-// TODO: traitify
+impl std::str::FromStr for Action {
+    type Err = ParseCoordError;
+
+    /// Parses e.g. `C4`, `pass` or `swap`.
+    fn from_str(s: &str) -> Result<Action, ParseCoordError> {
+        if s.eq_ignore_ascii_case("pass") {
+            Ok(Action::Pass)
+        } else if s.eq_ignore_ascii_case("swap") {
+            Ok(Action::Swap)
+        } else {
+            s.parse().map(Action::Move)
+        }
+    }
+}
+
+/// A Hobogo position, as seen by the AI.
 #[derive(Clone)]
 pub struct GameState {
     /// Who is making the next turn?
     pub next_player: Player,
 
-    pub num_players: usize,
-
     pub board: Board,
+
+    /// How many actions have been taken since the start of the game.
+    pub num_actions: usize,
+
+    /// The first mark of the game, if the next player may still take it over with [`Action::Swap`].
+    pub swappable: Option<Coord>,
 }
 
-pub type Score = Vec<f64>;
+impl PartialEq for GameState {
+    fn eq(&self, other: &GameState) -> bool {
+        self.next_player == other.next_player
+            && self.board == other.board
+            && self.swappable == other.swappable
+    }
+}
+
+impl Eq for GameState {}
+
+impl std::hash::Hash for GameState {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.zobrist());
+    }
+}
 
 impl GameState {
-    fn available_moves_for(&self, player: Player) -> Vec<Coord> {
-        self.board
-            .coords()
-            .filter(|c| self.board.is_valid_move(*c, player, self.num_players))
-            .collect()
+    /// A 64-bit hash of the board and whose turn it is.
+    pub fn zobrist(&self) -> u64 {
+        self.board.zobrist() ^ zobrist_turn_key(self.next_player)
     }
 
     fn available_actions_for(&self, player: Player) -> Vec<Action> {
-        let available_moves = self.available_moves_for(player);
-        if available_moves.is_empty() {
-            if self.board.is_game_over(self.num_players) {
-                vec![]
-            } else {
-                vec![Action::Pass]
-            }
-        } else {
-            available_moves.into_iter().map(Action::Move).collect()
+        let available_moves = self.board.valid_moves(player);
+        let mut actions: Vec<Action> = available_moves.into_iter().map(Action::Move).collect();
+        if self.swappable.is_some() && !self.board.is_game_over() {
+            actions.push(Action::Swap);
+        }
+        if actions.is_empty() && !self.board.is_game_over() {
+            actions.push(Action::Pass);
         }
+        actions
+    }
+}
+
+impl Game for GameState {
+    type Action = Action;
+
+    fn num_players(&self) -> usize {
+        self.board.num_players()
+    }
+
+    fn next_player(&self) -> usize {
+        self.next_player as usize
     }
 
     fn available_actions(&self) -> Vec<Action> {
         self.available_actions_for(self.next_player)
     }
 
+    fn is_game_over(&self) -> bool {
+        self.board.is_game_over()
+    }
+
     fn random_action<R: Rng>(&self, rng: &mut R) -> Option<Action> {
         // TODO: prefer smart actions
-        if false {
-            self.available_actions().choose(rng).cloned()
+        // Playouts never swap, so a swap is only ever considered in the tree.
+        if let Some(coord) = self.board.random_valid_move(rng, self.next_player) {
+            Some(Action::Move(coord))
+        } else if self.board.is_game_over() {
+            None
         } else {
-            // Faster
-            let mut coords: Vec<Coord> = self.board.coords().collect();
-            coords.shuffle(rng);
-            for coord in coords {
-                if self
-                    .board
-                    .is_valid_move(coord, self.next_player, self.num_players)
-                {
-                    return Some(Action::Move(coord));
-                }
-            }
-            if self.board.is_game_over(self.num_players) {
-                None
-            } else {
-                Some(Action::Pass)
-            }
+            Some(Action::Pass)
         }
     }
 
     fn take_action(&mut self, action: &Action) {
-        if let Action::Move(coord) = action {
-            self.board.set(*coord, self.next_player);
+        let swappable = match action {
+            Action::Move(coord) if self.num_actions == 0 && self.board.rules().swap => Some(*coord),
+            _ => None,
+        };
+        match action {
+            Action::Pass => {}
+            Action::Move(coord) => self.board.set(*coord, self.next_player),
+            Action::Swap => {
+                let coord = self.swappable.expect("Nothing to swap");
+                self.board.set(coord, self.next_player);
+            }
         }
-        self.next_player = (self.next_player + 1) % (self.num_players as u8);
+        self.num_actions += 1;
+        self.swappable = swappable;
+        self.next_player = (self.next_player + 1) % (self.num_players() as u8);
     }
 
     /// Only called when one player has no action to take (game over).
+    /// Teammates share the score of their team.
     fn score(&self) -> Score {
-        let points = self.board.points();
-        let mut winner = 0;
-        let mut runner_up = 0;
-        for player in 0..self.num_players {
-            if points[player] > points[winner] {
-                runner_up = winner;
-                winner = player;
-            } else if points[player] > runner_up {
-                runner_up = player;
-            }
-        }
-
-        debug_assert!(points[winner] >= points[runner_up]);
-
-        let tie = points[winner] == points[runner_up];
-
-        (0..self.num_players)
+        let result = self
+            .board
+            .game_result()
+            .expect("Scoring a game that isn't over");
+        let points = &result.points;
+        let winner_points = points[result.winners()[0] as usize];
+        let runner_up_points = result
+            .placements
+            .get(1)
+            .map_or(0, |group| points[group[0] as usize]);
+
+        let teams = self.board.teams();
+        (0..self.num_players())
             .map(|pi| {
-                let points_behind_winner = points[winner] - points[pi];
+                let team = teams.team_of(pi as Player);
+                let points_behind_winner = winner_points - points[team as usize];
                 if points_behind_winner == 0 {
-                    if tie {
+                    if result.is_tie() {
                         // One of several winners
                         0.5
                     } else {
                         // Sole winner
-                        let points_ahead = points[winner] - points[runner_up];
+                        let points_ahead = winner_points - runner_up_points;
                         1.0 + (points_ahead as f64) / 10.0 // Try to maximize our win margin
                     }
                 } else {
@@ -129,72 +230,36 @@ impl GameState {
             })
             .collect()
     }
+}
 
-    fn next_move_from_deque(
-        &self,
-        moves: &mut VecDeque<Coord>,
-        active_player: Player,
-    ) -> Option<Coord> {
-        for _ in 0..moves.len() {
-            let coord = moves.pop_front().unwrap();
-            match self.board.influence(coord) {
-                Influence::Occupied(_) => {}
-                Influence::Ruled(ruler) if ruler != active_player => {}
-                Influence::Claimed(claimer) if claimer != active_player => {
-                    moves.push_back(coord); // Try again later
-                }
-                _ => {
-                    return Some(coord);
-                }
-            };
-        }
-        None
-    }
-
-    fn random_playout<R: Rng>(&mut self, rng: &mut R) {
-        if false {
-            while let Some(action) = self.random_action(rng) {
-                self.take_action(&action)
-            }
-        } else {
-            // Almost twice as fast as the one above.
-
-            let all_moves: Vec<_> = self
-                .board
-                .coords()
-                .filter(|c| !self.board.influence(*c).is_occupied())
-                .collect();
-
-            // Keep a list of available moves for each player:
-            let mut player_moves = vec![all_moves; self.num_players];
-            for player in 0..self.num_players {
-                player_moves[player].shuffle(rng);
-            }
+// ----------------------------------------------------------------------------
 
-            let mut player_moves: Vec<VecDeque<Coord>> = player_moves
-                .into_iter()
-                .map(|v| v.into_iter().collect())
-                .collect();
-
-            loop {
-                let next_player = self.next_player;
-                let moves = &mut player_moves[next_player as usize];
-                if let Some(coord) = self.next_move_from_deque(moves, next_player) {
-                    self.take_action(&Action::Move(coord));
-                } else if self.board.is_game_over(self.num_players) {
-                    break;
-                } else {
-                    self.take_action(&Action::Pass);
-                }
-            }
-        }
+/// The natural logarithm of a positive number, using only basic arithmetic.
+///
+/// `f64::ln` comes from the platform's math library and may round the last bit differently
+/// on the web than natively, which could change which child the search explores,
+/// and so make a seeded search pick different actions on different platforms.
+fn portable_ln(x: f64) -> f64 {
+    debug_assert!(x.is_normal() && x > 0.0);
+    // x = m * 2^e with m in [1, 2):
+    let bits = x.to_bits();
+    let e = ((bits >> 52) & 0x7ff) as i64 - 1023;
+    let m = f64::from_bits((bits & ((1 << 52) - 1)) | (1023 << 52));
+    // ln(m) = 2 atanh(s) = 2 (s + s³/3 + s⁵/5 + …) with s = (m - 1) / (m + 1) < 1/3:
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let mut power = s;
+    let mut sum = 0.0;
+    for k in 0..20 {
+        sum += power / (2 * k + 1) as f64;
+        power *= s2;
     }
+    e as f64 * std::f64::consts::LN_2 + 2.0 * sum
 }
 
-// ----------------------------------------------------------------------------
-
 /// Each node has an implicit current player, passed down via the game state.
-struct Node {
+#[derive(Clone)]
+struct Node<A> {
     /// Number plays from this node
     num: usize,
 
@@ -202,11 +267,11 @@ struct Node {
     score_sum: f64,
 
     /// All available actions from here.
-    children: Option<Vec<(Action, Node)>>,
+    children: Option<Vec<(A, Node<A>)>>,
 }
 
-impl Node {
-    fn new() -> Node {
+impl<A: Copy + PartialEq + fmt::Display> Node<A> {
+    fn new() -> Node<A> {
         Node {
             num: 0,
             score_sum: 0.0,
@@ -214,11 +279,11 @@ impl Node {
         }
     }
 
-    fn children_mut<R: Rng>(
+    fn children_mut<R: Rng, G: Game<Action = A>>(
         &mut self,
         rng: &mut R,
-        state: &GameState,
-    ) -> impl Iterator<Item = &mut (Action, Node)> {
+        state: &G,
+    ) -> impl Iterator<Item = &mut (A, Node<A>)> {
         if self.children.is_none() {
             let mut actions = state.available_actions();
             use rand::prelude::*;
@@ -234,20 +299,20 @@ impl Node {
     }
 
     // Find the next child to recurse on
-    fn next_child<R: Rng>(
+    fn next_child<R: Rng, G: Game<Action = A>>(
         &mut self,
         rng: &mut R,
-        state: &GameState,
-    ) -> Option<(Action, &mut Node)> {
-        let mut best_value: f64 = std::f64::NEG_INFINITY;
+        state: &G,
+    ) -> Option<(A, &mut Node<A>)> {
+        let mut best_value: f64 = f64::NEG_INFINITY;
         let mut best = None;
 
-        let self_num_ln = (self.num as f64).ln();
+        let self_num_ln = portable_ln(self.num as f64);
 
-        for (action, child) in self.children_mut(rng, &state) {
+        for (action, child) in self.children_mut(rng, state) {
             if child.num == 0 {
                 // Unexpanded child – prioritize over all others
-                return Some((action.clone(), child));
+                return Some((*action, child));
             }
 
             // UCT (Upper Confidence Tree):
@@ -255,38 +320,65 @@ impl Node {
                 + (2.0 * self_num_ln / (child.num as f64)).sqrt();
             if value > best_value {
                 best_value = value;
-                best = Some((action.clone(), child))
+                best = Some((*action, child))
             }
         }
 
         best
     }
 
-    // Recursively play, returns the winner.
-    fn iterate<R: Rng>(&mut self, rng: &mut R, mut state: GameState) -> Score {
-        // Which player the current node is trying to win for:
-        // TODO: fix this uglyness:
-        let previous_player =
-            ((state.next_player as usize + state.num_players - 1) % state.num_players) as u8;
-        let optimizing_player = previous_player;
-
+    /// Recursively play, returns the score.
+    /// `optimizing_player` is the one who took the action leading here.
+    fn iterate<R: Rng, G: Game<Action = A>>(
+        &mut self,
+        rng: &mut R,
+        mut state: G,
+        optimizing_player: usize,
+    ) -> Score {
         let score = if self.num == 0 {
             state.random_playout(rng);
             state.score()
-        } else if let Some((action, child)) = self.next_child(rng, &state) {
-            state.take_action(&action);
-            child.iterate(rng, state)
         } else {
-            // No children. We are a leaf.
-            state.score()
+            let player = state.next_player();
+            if let Some((action, child)) = self.next_child(rng, &state) {
+                state.take_action(&action);
+                child.iterate(rng, state, player)
+            } else {
+                // No children. We are a leaf.
+                state.score()
+            }
         };
 
         self.num += 1;
-        self.score_sum += score[optimizing_player as usize];
+        self.score_sum += score[optimizing_player];
         score
     }
 
-    fn best_action(&self) -> Option<&Action> {
+    /// Add the statistics of another node for the same position to ours.
+    fn merge(&mut self, other: Node<A>) {
+        self.num += other.num;
+        self.score_sum += other.score_sum;
+        let other_children = match other.children {
+            Some(other_children) => other_children,
+            None => return,
+        };
+        match &mut self.children {
+            None => self.children = Some(other_children),
+            Some(children) => {
+                for (action, other_child) in other_children {
+                    match children
+                        .iter_mut()
+                        .find(|(child_action, _)| *child_action == action)
+                    {
+                        Some((_, child)) => child.merge(other_child),
+                        None => children.push((action, other_child)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn best_action(&self) -> Option<&A> {
         match &self.children {
             Some(children) => {
                 let mut best_action = None;
@@ -305,21 +397,25 @@ impl Node {
     }
 }
 
-impl fmt::Display for Node {
+impl<A: fmt::Display> fmt::Display for Node<A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fn fmt_subtree(f: &mut fmt::Formatter, node: &Node, indent_level: i32) -> fmt::Result {
+        fn fmt_subtree<A: fmt::Display>(
+            f: &mut fmt::Formatter,
+            node: &Node<A>,
+            indent_level: i32,
+        ) -> fmt::Result {
             writeln!(
                 f,
                 "mean score: {} over {} playouts",
-                (node.score_sum as f64) / (node.num as f64),
+                node.score_sum / (node.num as f64),
                 node.num
             )?;
             if indent_level >= 1 {
                 return Ok(());
             }
             if let Some(children) = &node.children {
-                let mut children: Vec<&(Action, Node)> = children.iter().collect();
-                children.sort_by_key(|(_, node)| std::usize::MAX - node.num);
+                let mut children: Vec<&(A, Node<A>)> = children.iter().collect();
+                children.sort_by_key(|(_, node)| usize::MAX - node.num);
                 for (action, child) in children.iter() {
                     if child.num > 0 {
                         for _ in 0..indent_level {
@@ -340,34 +436,211 @@ impl fmt::Display for Node {
 // ----------------------------------------------------------------------------
 
 /// Simple Monte Carlo Tree Search implementation.
-pub struct Mcts {
-    start_state: GameState,
-    root: Node,
+#[derive(Clone)]
+pub struct Mcts<G: Game = GameState> {
+    start_state: G,
+    root: Node<G::Action>,
 }
 
-impl Mcts {
-    pub fn new(state: GameState) -> Self {
+impl<G: Game> Mcts<G> {
+    pub fn new(state: G) -> Self {
         Mcts {
             start_state: state,
             root: Node::new(),
         }
     }
 
+    /// The position we are searching from.
+    pub fn state(&self) -> &G {
+        &self.start_state
+    }
+
+    /// Move on to the position after `action`, keeping what we have learned about it so far.
+    pub fn advance(&mut self, action: &G::Action) {
+        let subtree = self.root.children.take().and_then(|children| {
+            children
+                .into_iter()
+                .find(|(child_action, _)| child_action == action)
+                .map(|(_, child)| child)
+        });
+        self.root = subtree.unwrap_or_else(Node::new);
+        self.start_state.take_action(action);
+    }
+
     pub fn iterate<R: Rng>(&mut self, rng: &mut R) {
-        self.root.iterate(rng, self.start_state.clone());
+        // The root is scored for whoever moved before it:
+        let num_players = self.start_state.num_players();
+        let previous_player = (self.start_state.next_player() + num_players - 1) % num_players;
+        self.root
+            .iterate(rng, self.start_state.clone(), previous_player);
     }
 
-    pub fn best_action(&self) -> Option<&Action> {
+    pub fn best_action(&self) -> Option<&G::Action> {
         self.root.best_action()
     }
 
     pub fn num_iterations(&self) -> usize {
         self.root.num
     }
+
+    /// Add what another search from the same position has learned to this one.
+    pub fn merge(&mut self, other: Mcts<G>) {
+        self.root.merge(other.root);
+    }
+
+    /// Is the most explored action so far ahead that this many more iterations
+    /// could not make another action catch up with it?
+    pub fn is_decided(&self, remaining_iterations: usize) -> bool {
+        let children = match &self.root.children {
+            Some(children) => children,
+            None => return false,
+        };
+        let mut most = 0;
+        let mut second_most = 0;
+        for (_, child) in children {
+            if child.num > most {
+                second_most = most;
+                most = child.num;
+            } else if child.num > second_most {
+                second_most = child.num;
+            }
+        }
+        most > second_most + remaining_iterations
+    }
 }
 
-impl fmt::Display for Mcts {
+impl<G: Game> fmt::Display for Mcts<G> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.root.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::ai::AiRng;
+    use crate::rules::RuleSet;
+    use crate::tictactoe::TicTacToe;
+
+    fn searched(game: TicTacToe, iterations: usize) -> Mcts<TicTacToe> {
+        let mut mcts = Mcts::new(game);
+        let mut rng = AiRng::seed_from_u64(0);
+        for _ in 0..iterations {
+            mcts.iterate(&mut rng);
+        }
+        mcts
+    }
+
+    /// Visits of each child of the root.
+    fn child_visits<A: Copy>(node: &Node<A>) -> Vec<(A, usize)> {
+        node.children
+            .iter()
+            .flatten()
+            .map(|(action, child)| (*action, child.num))
+            .collect()
+    }
+
+    #[test]
+    fn advance_keeps_the_subtree() {
+        let mut mcts = searched(TicTacToe::new(), 2_000);
+        let (_, center) = mcts
+            .root
+            .children
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|(action, _)| *action == 4)
+            .unwrap();
+        let (num, score_sum, grandchildren) = (center.num, center.score_sum, child_visits(center));
+        assert!(num > 0 && !grandchildren.is_empty());
+
+        mcts.advance(&4);
+        assert_eq!(*mcts.state(), TicTacToe::from_moves(&[4]));
+        assert_eq!(mcts.num_iterations(), num);
+        assert_eq!(mcts.root.score_sum, score_sum);
+        assert_eq!(child_visits(&mcts.root), grandchildren);
+
+        // And we can go on searching from there:
+        let mut rng = AiRng::seed_from_u64(1);
+        mcts.iterate(&mut rng);
+        assert_eq!(mcts.num_iterations(), num + 1);
+    }
+
+    #[test]
+    fn advance_past_the_tree_starts_over() {
+        // Nothing searched yet:
+        let mut mcts = Mcts::new(TicTacToe::new());
+        mcts.advance(&4);
+        assert_eq!(*mcts.state(), TicTacToe::from_moves(&[4]));
+        assert_eq!(mcts.num_iterations(), 0);
+        assert!(mcts.root.children.is_none());
+
+        // Searched, but not deep enough to have expanded anything after the second move:
+        let mut mcts = searched(TicTacToe::new(), 9);
+        mcts.advance(&4);
+        assert!(mcts.num_iterations() <= 1);
+        mcts.advance(&0);
+        assert_eq!(*mcts.state(), TicTacToe::from_moves(&[4, 0]));
+        assert_eq!(mcts.num_iterations(), 0);
+        assert!(mcts.root.children.is_none());
+
+        let mut rng = AiRng::seed_from_u64(1);
+        for _ in 0..100 {
+            mcts.iterate(&mut rng);
+        }
+        assert_eq!(mcts.num_iterations(), 100);
+    }
+
+    #[test]
+    fn no_swap_after_the_game_is_over() {
+        let rules = RuleSet {
+            swap: true,
+            ..RuleSet::default()
+        };
+        let opened = |width: i32, c: Coord| {
+            let board = Board::new(width, 1, 2).with_rules(rules);
+            let mut game = crate::game::Game::new(board.clone(), 0);
+            game.play(Action::Move(c)).unwrap();
+            let mut state = GameState {
+                next_player: 0,
+                board,
+                num_actions: 0,
+                swappable: None,
+            };
+            state.take_action(&Action::Move(c));
+            assert_eq!(state.available_actions(), game.valid_actions());
+            state
+        };
+
+        // X in the middle outnumbers O everywhere:
+        let state = opened(3, Coord::new(1, 0));
+        assert!(state.is_game_over());
+        assert_eq!(state.available_actions(), vec![]);
+        assert_eq!(crate::ai::ai_action_seeded(state, 0, 100), None);
+
+        let state = opened(4, Coord::new(0, 0));
+        assert!(state.available_actions().contains(&Action::Swap));
+    }
+
+    #[test]
+    fn portable_ln_matches_ln() {
+        let mut x = f64::MIN_POSITIVE;
+        while x < 1e300 {
+            for &y in &[x, x * 1.234_567, x * 1.999_999] {
+                let expected = y.ln();
+                let error = (portable_ln(y) - expected).abs() / expected.abs().max(1.0);
+                assert!(
+                    error < 1e-15,
+                    "ln({}): {} vs {}",
+                    y,
+                    portable_ln(y),
+                    expected
+                );
+            }
+            x *= 3.0;
+        }
+        assert_eq!(portable_ln(1.0), 0.0);
+    }
+}
@@ -0,0 +1,203 @@
+//! Root-parallel MCTS: every thread grows its own tree from the same position,
+//! and the trees are merged into one at the end. The first thread grows the tree we already
+//! had, e.g. kept from the previous move, the others grow new ones.
+//!
+//! The threads share nothing while searching, so there is no locking and no virtual loss,
+//! at the price of each thread exploring some of the same lines as the others.
+//! Only with the `parallel` feature, and never on wasm, which has no threads.
+
+use rand::{Rng, SeedableRng};
+
+use crate::ai::{self, AiRng, Budget};
+use crate::mcts::Mcts;
+
+/// How many threads [`search`] uses: one per core.
+pub fn num_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Like [`ai::search`], but on all cores.
+pub fn search(mcts: &mut Mcts, budget: Budget, num_actions: usize, rng: &mut AiRng) {
+    search_on_threads(mcts, budget, num_actions, rng, num_threads());
+}
+
+/// Like [`ai::search`], but on `num_threads` threads.
+///
+/// A budget of iterations is split between the threads, while a budget of time is given
+/// to each of them. The first thread carries on growing `mcts`, so that what it already
+/// knows guides the search, and the others' trees are merged into it at the end.
+/// The result only depends on `rng` and `num_threads`, not on timing, as long as the budget
+/// is iterations.
+pub fn search_on_threads(
+    mcts: &mut Mcts,
+    budget: Budget,
+    num_actions: usize,
+    rng: &mut AiRng,
+    num_threads: usize,
+) {
+    if num_threads <= 1 {
+        return ai::search(mcts, budget, num_actions, rng);
+    }
+
+    let budgets: Vec<Budget> = match budget {
+        Budget::Iterations(iterations) => (0..num_threads)
+            .map(|thread| Budget::Iterations((iterations + thread) / num_threads))
+            .collect(),
+        _ => vec![budget; num_threads],
+    };
+    let seeds: Vec<u64> = (0..num_threads).map(|_| rng.gen()).collect();
+    let state = mcts.state().clone();
+    // The others start from scratch, or merging their trees would count what we had again:
+    let retained = std::mem::replace(mcts, Mcts::new(state.clone()));
+    let starts =
+        std::iter::once(retained).chain((1..num_threads).map(|_| Mcts::new(state.clone())));
+
+    let trees: Vec<Mcts> = std::thread::scope(|scope| {
+        let threads: Vec<_> = starts
+            .zip(budgets)
+            .zip(seeds)
+            .map(|((mut tree, budget), seed)| {
+                scope.spawn(move || {
+                    ai::search(
+                        &mut tree,
+                        budget,
+                        num_actions,
+                        &mut AiRng::seed_from_u64(seed),
+                    );
+                    tree
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().expect("MCTS thread panicked"))
+            .collect()
+    });
+
+    let mut trees = trees.into_iter();
+    *mcts = trees.next().expect("at least two threads");
+    for tree in trees {
+        mcts.merge(tree);
+    }
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use super::*;
+    use crate::hobogo::{Board, Coord};
+    use crate::mcts::{Action, Game, GameState};
+    use crate::solver;
+
+    fn empty_state(size: i32) -> GameState {
+        GameState {
+            next_player: 0,
+            board: Board::new(size, size, 2),
+            num_actions: 0,
+            swappable: None,
+        }
+    }
+
+    fn searched(state: &GameState, budget: Budget, seed: u64, num_threads: usize) -> Mcts {
+        let mut mcts = Mcts::new(state.clone());
+        let num_actions = state.available_actions().len();
+        let mut rng = AiRng::seed_from_u64(seed);
+        search_on_threads(&mut mcts, budget, num_actions, &mut rng, num_threads);
+        mcts
+    }
+
+    /// Random positions with few enough free cells to solve, where exactly one action is best.
+    fn decided_positions(num_positions: usize) -> Vec<(GameState, Action)> {
+        let mut rng = AiRng::seed_from_u64(0);
+        let mut positions = vec![];
+        while positions.len() < num_positions {
+            let mut state = empty_state(5);
+            while state.board.num_free_cells() > 10 && !state.is_game_over() {
+                let actions = state.available_actions();
+                state.take_action(&actions[rng.gen_range(0, actions.len())]);
+            }
+            if state.is_game_over() {
+                continue;
+            }
+            let value = |state: &GameState| solver::solve(state, usize::MAX).unwrap().value;
+            let best_value = value(&state);
+            let best: Vec<Action> = state
+                .available_actions()
+                .into_iter()
+                .filter(|action| {
+                    let mut child = state.clone();
+                    child.take_action(action);
+                    -value(&child) == best_value
+                })
+                .collect();
+            if let [best] = best[..] {
+                positions.push((state, best));
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn finds_the_same_action_as_one_thread() {
+        // Enough for one thread to find the best action in each of these positions:
+        let budget = Budget::Iterations(2_000);
+        for (seed, (state, best)) in decided_positions(10).iter().enumerate() {
+            for &num_threads in &[1, 2, 4] {
+                let found = searched(state, budget, seed as u64, num_threads)
+                    .best_action()
+                    .copied();
+                assert_eq!(
+                    found,
+                    Some(*best),
+                    "{} threads, position {}:\n{}",
+                    num_threads,
+                    seed,
+                    state.board
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn carries_on_with_the_tree_it_has() {
+        // The only winning move, see the solver tests, which a long search has made certain of:
+        let state = GameState {
+            board: Board::new(3, 1, 2),
+            ..empty_state(1)
+        };
+        let mut mcts = searched(&state, Budget::Iterations(1_000), 0, 1);
+        let before = mcts.num_iterations();
+        assert!(mcts.is_decided(1));
+
+        // One iteration each: the thread with the tree we had sees there is nothing left to
+        // decide, while the other one, starting from scratch, does its iteration:
+        let mut rng = AiRng::seed_from_u64(0);
+        search_on_threads(&mut mcts, Budget::Iterations(2), 3, &mut rng, 2);
+        assert_eq!(mcts.num_iterations(), before + 1);
+        assert_eq!(mcts.best_action(), Some(&Action::Move(Coord::new(1, 0))));
+    }
+
+    /// Timing depends on the machine and what else runs on it, so this is not part of the suite.
+    /// Run with `cargo test --release --features parallel -- --ignored throughput_scales`.
+    #[test]
+    #[ignore]
+    fn throughput_scales_with_threads() {
+        let seconds = 0.5;
+        // Nothing is decided early on an empty board, so every thread runs for the whole time:
+        let state = empty_state(9);
+        let iterations_per_second = |num_threads| {
+            searched(&state, Budget::Seconds(seconds), 0, num_threads).num_iterations() as f64
+                / seconds
+        };
+        let num_threads = num_threads().clamp(2, 4);
+        let speedup = iterations_per_second(num_threads) / iterations_per_second(1);
+        // On a single core the threads can only take turns, so expect no speedup there:
+        let expected = num_threads.min(super::num_threads()) as f64;
+        assert!(
+            speedup > 0.6 * expected,
+            "{} threads on {} cores: {:.2}x",
+            num_threads,
+            super::num_threads(),
+            speedup
+        );
+    }
+}
@@ -0,0 +1,545 @@
+//! A plain-text format for recording Hobogo games.
+//!
+//! ```text
+//! ; Comments run from a semicolon to the end of the line.
+//! Size: 9x9
+//! Players: 2
+//! First: 0
+//! Rules: standard
+//! Topology: square
+//!
+//! E5 D4
+//! E4 PASS
+//! ```
+//!
+//! The header is a list of `Key: value` lines:
+//!
+//! * `Size` – board width and height, e.g. `9x9`, followed by the number of layers
+//!   for layered boards, e.g. `5x5x3`. Required.
+//! * `Players` – the number of players. Required.
+//! * `First` – the player who makes the first move. Players are numbered from 0. Defaults to 0.
+//! * `Rules` – `standard`, or a comma-separated list of how the rules differ from the
+//!   standard ones, e.g. `margin=2,ties-block`. See [`RuleSet`]. Defaults to `standard`.
+//! * `Topology` – `square`, `square4` (four neighbors) or `hex`,
+//!   followed by `,torus` if the board wraps around. See [`Topology`]. Defaults to `square`.
+//! * `Map` – which cells are blocked, as rows of `.` and `#` separated by `/`, e.g. `#.#/.../#.#`.
+//!   Must match `Size`. See [`Map`]. Defaults to no blocked cells.
+//! * `Teams` – the players of each team joined by `+`, with the teams separated by commas,
+//!   e.g. `0+2,1+3`. Must cover all players. See [`Teams`]. Defaults to everyone for themselves.
+//! * `Setup` – marks placed before the first move, such as handicaps, as cells and players,
+//!   e.g. `C3=1,G7=1`. See [`Setup`]. Defaults to an empty board.
+//!
+//! After the header comes the list of moves in the order they were played,
+//! separated by whitespace. Each move is either a cell name like `C4` or `AA12`
+//! (column letters followed by the row number, counting from 1), `PASS`,
+//! or `SWAP` for taking over the first mark under the swap rule.
+//! On layered boards, cells above the first layer get the layer number after a slash, e.g. `C4/2`.
+//! The writer puts one round of moves on each line.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::game::Game;
+use crate::hobogo::{is_valid_num_players, Board, HobogoError, Player};
+use crate::map::Map;
+use crate::mcts::Action;
+use crate::rules::RuleSet;
+use crate::setup::Setup;
+use crate::teams::Teams;
+use crate::topology::Topology;
+
+/// Everything needed to replay a game.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    pub width: i32,
+    pub height: i32,
+    /// Number of layers. 1 for flat boards.
+    pub depth: i32,
+    pub num_players: usize,
+    pub first_player: Player,
+    pub rules: RuleSet,
+    pub topology: Topology,
+    /// `None` if no cells are blocked.
+    pub map: Option<Map>,
+    /// `None` if everyone plays for themselves.
+    pub teams: Option<Teams>,
+    /// `None` if the game starts from an empty board.
+    pub setup: Option<Setup>,
+    /// All moves, in the order they were played.
+    pub moves: Vec<Action>,
+}
+
+impl GameRecord {
+    pub fn new(width: i32, height: i32, num_players: usize, first_player: Player) -> Self {
+        GameRecord {
+            width,
+            height,
+            depth: 1,
+            num_players,
+            first_player,
+            rules: RuleSet::default(),
+            topology: Topology::SQUARE,
+            map: None,
+            teams: None,
+            setup: None,
+            moves: vec![],
+        }
+    }
+
+    /// Record everything played so far in a game.
+    pub fn from_game(game: &Game) -> Self {
+        GameRecord {
+            width: game.board().width,
+            height: game.board().height,
+            depth: game.board().depth,
+            num_players: game.num_players(),
+            first_player: game.first_player(),
+            rules: *game.board().rules(),
+            topology: game.board().topology(),
+            map: Some(game.board().map()).filter(|map| !map.is_full()),
+            teams: Some(game.board().teams().clone()).filter(|teams| !teams.is_solo()),
+            setup: Some(game.setup().clone()).filter(|setup| !setup.is_empty()),
+            moves: game.history().to_vec(),
+        }
+    }
+
+    /// Place the setup and play all the moves from the start, checking that each one is legal.
+    pub fn replay(&self) -> Result<Game, ReplayError> {
+        let map = match &self.map {
+            Some(map) => map.clone(),
+            None => Map::full(self.width, self.height),
+        };
+        let mut board = Board::from_map(&map, self.num_players)
+            .with_topology(self.topology)
+            .with_layers(self.depth)
+            .with_rules(self.rules)
+            .with_teams(
+                self.teams
+                    .clone()
+                    .unwrap_or_else(|| Teams::solo(self.num_players)),
+            );
+        if let Some(setup) = &self.setup {
+            setup
+                .place_on(&mut board)
+                .map_err(|(coord, error)| ReplayError {
+                    move_number: 0,
+                    action: Action::Move(coord),
+                    error,
+                })?;
+        }
+        let mut game = Game::new(board, self.first_player);
+
+        for (move_index, &action) in self.moves.iter().enumerate() {
+            game.play(action).map_err(|error| ReplayError {
+                move_number: move_index + 1,
+                action,
+                error,
+            })?;
+        }
+
+        Ok(game)
+    }
+}
+
+/// A move in a [`GameRecord`] that breaks the rules.
+#[derive(Clone, Debug)]
+pub struct ReplayError {
+    /// Counting from 1, or 0 for a mark of the setup.
+    pub move_number: usize,
+    pub action: Action,
+    pub error: HobogoError,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.move_number == 0 {
+            write!(f, "Setup ({}): {}", self.action, self.error)
+        } else {
+            write!(
+                f,
+                "Move {} ({}): {}",
+                self.move_number, self.action, self.error
+            )
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Something wrong in the text of a [`GameRecord`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// Counting from 1.
+    pub line: usize,
+    /// Counting from 1, in characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Split a line into whitespace-separated words, with their (1-based) columns.
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (column, (byte_index, c)) in line.char_indices().enumerate() {
+        if c.is_whitespace() {
+            if let Some((start_column, start_byte)) = start.take() {
+                words.push((start_column + 1, &line[start_byte..byte_index]));
+            }
+        } else if start.is_none() {
+            start = Some((column, byte_index));
+        }
+    }
+    if let Some((start_column, start_byte)) = start {
+        words.push((start_column + 1, &line[start_byte..]));
+    }
+    words.into_iter()
+}
+
+impl FromStr for GameRecord {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<GameRecord, ParseError> {
+        let mut size = None;
+        let mut num_players = None;
+        let mut first_player = None;
+        let mut rules = None;
+        let mut topology = None;
+        let mut map = None;
+        let mut teams = None;
+        let mut setup = None;
+        let mut moves = vec![];
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line_nr = line_index + 1;
+            let error = |column: usize, message: String| ParseError {
+                line: line_nr,
+                column,
+                message,
+            };
+
+            let line = match line.find(';') {
+                Some(comment_start) => &line[..comment_start],
+                None => line,
+            };
+
+            if let Some(colon) = line.find(':') {
+                let key = line[..colon].trim();
+                let key_column = line[..colon]
+                    .chars()
+                    .take_while(|c| c.is_whitespace())
+                    .count()
+                    + 1;
+                if !moves.is_empty() {
+                    return Err(error(key_column, "Header after the moves".to_owned()));
+                }
+
+                let value_column = line[..=colon].chars().count() + 1;
+                let (value_column, value) = match words(&line[colon + 1..]).collect::<Vec<_>>()[..]
+                {
+                    [(column, value)] => (value_column + column - 1, value),
+                    _ => {
+                        return Err(error(
+                            value_column,
+                            format!("Expected a single value for '{}'", key),
+                        ))
+                    }
+                };
+                let bad_value =
+                    |what: &str| error(value_column, format!("Bad {}: '{}'", what, value));
+
+                let slot_was_empty = match key {
+                    "Size" => {
+                        let parts: Option<Vec<i32>> =
+                            value.split('x').map(|part| part.parse().ok()).collect();
+                        match parts.as_deref() {
+                            Some(&[width, height]) if width >= 1 && height >= 1 => {
+                                size.replace((width, height, 1)).is_none()
+                            }
+                            Some(&[width, height, depth])
+                                if width >= 1 && height >= 1 && depth >= 1 =>
+                            {
+                                size.replace((width, height, depth)).is_none()
+                            }
+                            _ => return Err(bad_value("size")),
+                        }
+                    }
+                    "Players" => match value.parse::<usize>() {
+                        Ok(n) if is_valid_num_players(n) => num_players.replace(n).is_none(),
+                        _ => return Err(bad_value("number of players")),
+                    },
+                    "First" => match value.parse::<Player>() {
+                        Ok(player) => first_player
+                            .replace((player, line_nr, value_column))
+                            .is_none(),
+                        _ => return Err(bad_value("player")),
+                    },
+                    "Rules" => match value.parse::<RuleSet>() {
+                        Ok(value) => rules.replace(value).is_none(),
+                        Err(err) => return Err(error(value_column, err.to_string())),
+                    },
+                    "Topology" => match value.parse::<Topology>() {
+                        Ok(value) => topology.replace(value).is_none(),
+                        Err(err) => return Err(error(value_column, err.to_string())),
+                    },
+                    "Map" => match value.parse::<Map>() {
+                        Ok(value) => map.replace((value, line_nr, value_column)).is_none(),
+                        Err(err) => return Err(error(value_column, err.to_string())),
+                    },
+                    "Teams" => match value.parse::<Teams>() {
+                        Ok(value) => teams.replace((value, line_nr, value_column)).is_none(),
+                        Err(err) => return Err(error(value_column, err.to_string())),
+                    },
+                    "Setup" => match value.parse::<Setup>() {
+                        Ok(value) => setup.replace((value, line_nr, value_column)).is_none(),
+                        Err(err) => return Err(error(value_column, err.to_string())),
+                    },
+                    _ => return Err(error(key_column, format!("Unknown header '{}'", key))),
+                };
+
+                if !slot_was_empty {
+                    return Err(error(key_column, format!("'{}' given twice", key)));
+                }
+            } else {
+                for (column, word) in words(line) {
+                    match word.parse() {
+                        Ok(action) => moves.push(action),
+                        Err(err) => return Err(error(column, format!("Bad move: {}", err))),
+                    }
+                }
+            }
+        }
+
+        let end_of_text = || ParseError {
+            line: text.lines().count().max(1),
+            column: 1,
+            message: String::new(),
+        };
+
+        let (width, height, depth) = size.ok_or_else(|| ParseError {
+            message: "Missing 'Size'".to_owned(),
+            ..end_of_text()
+        })?;
+        let num_players = num_players.ok_or_else(|| ParseError {
+            message: "Missing 'Players'".to_owned(),
+            ..end_of_text()
+        })?;
+        let map = match map {
+            Some((map, _, _)) if (map.width(), map.height()) == (width, height) => Some(map),
+            Some((map, line, column)) => {
+                return Err(ParseError {
+                    line,
+                    column,
+                    message: format!(
+                        "The map is {}x{}, but the size is {}x{}",
+                        map.width(),
+                        map.height(),
+                        width,
+                        height
+                    ),
+                });
+            }
+            None => None,
+        };
+        let teams = match teams {
+            Some((teams, _, _)) if teams.num_players() == num_players => Some(teams),
+            Some((teams, line, column)) => {
+                return Err(ParseError {
+                    line,
+                    column,
+                    message: format!(
+                        "The teams have {} players, but there are {}",
+                        teams.num_players(),
+                        num_players
+                    ),
+                });
+            }
+            None => None,
+        };
+        let setup = match setup {
+            Some((setup, line, column)) => {
+                if let Some(&(_, player)) = setup
+                    .marks()
+                    .iter()
+                    .find(|&&(_, player)| num_players <= player as usize)
+                {
+                    return Err(ParseError {
+                        line,
+                        column,
+                        message: format!("There is no player {}", player),
+                    });
+                }
+                Some(setup).filter(|setup| !setup.is_empty())
+            }
+            None => None,
+        };
+        let first_player = match first_player {
+            Some((player, _, _)) if (player as usize) < num_players => player,
+            Some((player, line, column)) => {
+                return Err(ParseError {
+                    line,
+                    column,
+                    message: format!("There is no player {}", player),
+                });
+            }
+            None => 0,
+        };
+
+        Ok(GameRecord {
+            width,
+            height,
+            depth,
+            num_players,
+            first_player,
+            rules: rules.unwrap_or_default(),
+            topology: topology.unwrap_or_default(),
+            map,
+            teams,
+            setup,
+            moves,
+        })
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.depth > 1 {
+            writeln!(f, "Size: {}x{}x{}", self.width, self.height, self.depth)?;
+        } else {
+            writeln!(f, "Size: {}x{}", self.width, self.height)?;
+        }
+        writeln!(f, "Players: {}", self.num_players)?;
+        writeln!(f, "First: {}", self.first_player)?;
+        writeln!(f, "Rules: {}", self.rules)?;
+        writeln!(f, "Topology: {}", self.topology)?;
+        if let Some(map) = &self.map {
+            writeln!(f, "Map: {}", map.to_line())?;
+        }
+        if let Some(teams) = &self.teams {
+            writeln!(f, "Teams: {}", teams)?;
+        }
+        if let Some(setup) = &self.setup {
+            writeln!(f, "Setup: {}", setup)?;
+        }
+        writeln!(f)?;
+        for round in self.moves.chunks(self.num_players.max(1)) {
+            let round: Vec<String> = round.iter().map(|action| action.to_string()).collect();
+            writeln!(f, "{}", round.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::ai::AiRng;
+    use crate::map::bundled_map;
+    use crate::topology::Neighborhood;
+
+    /// A game with random settings and random (valid) moves.
+    fn random_game(rng: &mut AiRng) -> Game {
+        let num_players = rng.gen_range(2, 5);
+        let map = if rng.gen_range(0, 4) == 0 {
+            bundled_map("Donut").unwrap()
+        } else {
+            Map::full(rng.gen_range(3, 8), rng.gen_range(3, 8))
+        };
+        let neighborhoods = [
+            Neighborhood::Moore,
+            Neighborhood::VonNeumann,
+            Neighborhood::Hex,
+        ];
+        let rules = RuleSet {
+            claim_margin: rng.gen_range(1, 3),
+            ties_allow_placement: rng.gen(),
+            swap: rng.gen(),
+            ..RuleSet::default()
+        };
+        let mut board = Board::from_map(&map, num_players)
+            .with_topology(Topology {
+                neighborhood: neighborhoods[rng.gen_range(0, neighborhoods.len())],
+                wrap: rng.gen(),
+            })
+            .with_layers(rng.gen_range(1, 3))
+            .with_rules(rules);
+        if num_players == 4 && rng.gen() {
+            board = board.with_teams(Teams::round_robin(4, 2));
+        }
+        if rng.gen() {
+            let c = board.coords().next().unwrap();
+            board.set(c, num_players as Player - 1);
+        }
+        let mut game = Game::new(board, rng.gen_range(0, num_players) as Player);
+        for _ in 0..rng.gen_range(0, 40) {
+            let actions = game.valid_actions();
+            if actions.is_empty() {
+                break;
+            }
+            game.play(actions[rng.gen_range(0, actions.len())]).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = AiRng::seed_from_u64(5);
+        for _ in 0..100 {
+            let game = random_game(&mut rng);
+            let record = GameRecord::from_game(&game);
+            let text = record.to_string();
+            let parsed: GameRecord = text
+                .parse()
+                .unwrap_or_else(|err| panic!("{}\n{}", err, text));
+            assert_eq!(parsed, record, "{}", text);
+
+            let replayed = parsed.replay().unwrap();
+            assert!(replayed.board() == game.board(), "{}", text);
+            assert_eq!(replayed.history(), game.history());
+            assert_eq!(replayed.next_player(), game.next_player());
+            assert_eq!(replayed.setup(), game.setup());
+        }
+    }
+
+    fn parse_error(text: &str) -> (usize, usize, String) {
+        let err = text.parse::<GameRecord>().unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn parse_error_positions() {
+        let (line, column, message) = parse_error("Size: 9x9\nPlayers: 2\n\nE5 D4\nE4  Z0 D5\n");
+        assert_eq!((line, column), (5, 5), "{}", message);
+
+        let (line, column, message) = parse_error("Size: 9x9\n  Playrs: 2\n");
+        assert_eq!((line, column), (2, 3));
+        assert_eq!(message, "Unknown header 'Playrs'");
+
+        let (line, column, message) = parse_error("Size: 9x9\nPlayers:   two ; comment\n");
+        assert_eq!((line, column), (2, 12));
+        assert_eq!(message, "Bad number of players: 'two'");
+
+        let (line, column, message) = parse_error("Size: 9x9\nPlayers: 2\nFirst: 2\n");
+        assert_eq!((line, column), (3, 8));
+        assert_eq!(message, "There is no player 2");
+
+        let (line, column, message) = parse_error("Size: 9x9\nPlayers: 2\n\nE5\nFirst: 1\n");
+        assert_eq!((line, column), (5, 1));
+        assert_eq!(message, "Header after the moves");
+
+        let (line, column, message) = parse_error("Size: 9x9\nSize: 9x9\nPlayers: 2\n");
+        assert_eq!((line, column), (2, 1));
+        assert_eq!(message, "'Size' given twice");
+
+        let (line, _, message) = parse_error("Size: 9x9\n\nE5\n");
+        assert_eq!(line, 3);
+        assert_eq!(message, "Missing 'Players'");
+    }
+}
@@ -0,0 +1,199 @@
+//! The rule options a game can be played with.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Which variant of the rules a game is played with.
+///
+/// The default is the standard rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RuleSet {
+    /// How many more neighbors than everyone else you need around a free cell to claim it.
+    /// Nobody else may move into a cell you claim. At least 1.
+    #[serde(deserialize_with = "deserialize_claim_margin")]
+    pub claim_margin: u8,
+
+    /// May you move into a cell where an enemy has exactly as many neighbors as you?
+    /// Cells where nobody has any neighbors are always open.
+    pub ties_allow_placement: bool,
+
+    /// End the game when nothing can change who gets which cell anymore.
+    /// Only if claimed cells count (see [`RuleSet::ends_when_settled`]).
+    pub end_when_settled: bool,
+
+    /// End the game when every free cell is ruled by someone.
+    /// Only if claimed cells count (see [`RuleSet::ends_when_all_ruled`]).
+    pub end_when_all_ruled: bool,
+
+    /// End the game when one player is so far ahead that nobody can catch up.
+    pub end_on_unbeatable_lead: bool,
+
+    /// Do claimed cells count as points, or only occupied ones?
+    pub count_claimed: bool,
+
+    /// The swap (pie) rule: instead of making the second move of the game,
+    /// the second player may take over the first mark.
+    pub swap: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            claim_margin: 1,
+            ties_allow_placement: true,
+            end_when_settled: true,
+            end_when_all_ruled: false,
+            end_on_unbeatable_lead: false,
+            count_claimed: true,
+            swap: false,
+        }
+    }
+}
+
+/// A claim margin of 0 would let you claim cells where you are behind.
+fn is_valid_claim_margin(margin: u8) -> bool {
+    margin >= 1
+}
+
+fn deserialize_claim_margin<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let margin = u8::deserialize(deserializer)?;
+    if is_valid_claim_margin(margin) {
+        Ok(margin)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "bad claim margin: {}",
+            margin
+        )))
+    }
+}
+
+impl RuleSet {
+    pub fn is_standard(&self) -> bool {
+        *self == RuleSet::default()
+    }
+
+    /// Does the game end when nothing can change who gets which cell anymore?
+    ///
+    /// Not if only occupied cells count: then a cell is only worth something
+    /// once someone has moved there, so the game has to be played out.
+    pub fn ends_when_settled(&self) -> bool {
+        self.end_when_settled && self.count_claimed
+    }
+
+    /// Does the game end when every free cell is ruled by someone?
+    /// Like [`RuleSet::ends_when_settled`], not if only occupied cells count.
+    pub fn ends_when_all_ruled(&self) -> bool {
+        self.end_when_all_ruled && self.count_claimed
+    }
+}
+
+/// Written as `standard`, or as a comma-separated list of how the rules
+/// differ from the standard rules, e.g. `margin=2,ties-block,lead-ends,swap`.
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_standard() {
+            return f.write_str("standard");
+        }
+
+        let mut options = vec![];
+        if self.claim_margin != 1 {
+            options.push(format!("margin={}", self.claim_margin));
+        }
+        if !self.ties_allow_placement {
+            options.push("ties-block".to_owned());
+        }
+        if !self.end_when_settled {
+            options.push("no-settled-end".to_owned());
+        }
+        if self.end_when_all_ruled {
+            options.push("all-ruled-ends".to_owned());
+        }
+        if self.end_on_unbeatable_lead {
+            options.push("lead-ends".to_owned());
+        }
+        if !self.count_claimed {
+            options.push("stones-only".to_owned());
+        }
+        if self.swap {
+            options.push("swap".to_owned());
+        }
+        f.write_str(&options.join(","))
+    }
+}
+
+/// Why a [`RuleSet`] could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseRuleSetError(String);
+
+impl fmt::Display for ParseRuleSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown rule option '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseRuleSetError {}
+
+impl FromStr for RuleSet {
+    type Err = ParseRuleSetError;
+
+    fn from_str(s: &str) -> Result<RuleSet, ParseRuleSetError> {
+        let mut rules = RuleSet::default();
+        for option in s.split(',').map(str::trim) {
+            let unknown = || ParseRuleSetError(option.to_owned());
+            match option {
+                "standard" => {}
+                "ties-block" => rules.ties_allow_placement = false,
+                "no-settled-end" => rules.end_when_settled = false,
+                "all-ruled-ends" => rules.end_when_all_ruled = true,
+                "lead-ends" => rules.end_on_unbeatable_lead = true,
+                "stones-only" => rules.count_claimed = false,
+                "swap" => rules.swap = true,
+                _ => {
+                    let margin = option.strip_prefix("margin=").ok_or_else(unknown)?;
+                    match margin.parse() {
+                        Ok(margin) if is_valid_claim_margin(margin) => rules.claim_margin = margin,
+                        _ => return Err(unknown()),
+                    }
+                }
+            }
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_and_serde_accept_the_same_margins() {
+        for margin in 0..=3u8 {
+            let from_text = format!("margin={}", margin).parse::<RuleSet>().ok();
+            let json = format!("{{\"claim_margin\": {}}}", margin);
+            let from_json = serde_json::from_str::<RuleSet>(&json).ok();
+            assert_eq!(from_text, from_json, "margin {}", margin);
+            assert_eq!(from_text.is_some(), margin >= 1);
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let rules = RuleSet {
+            claim_margin: 2,
+            ties_allow_placement: false,
+            end_when_settled: false,
+            end_when_all_ruled: true,
+            end_on_unbeatable_lead: true,
+            count_claimed: false,
+            swap: true,
+        };
+        assert_eq!(rules.to_string().parse::<RuleSet>(), Ok(rules));
+        assert_eq!(
+            RuleSet::default().to_string().parse::<RuleSet>(),
+            Ok(RuleSet::default())
+        );
+    }
+}
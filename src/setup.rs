@@ -0,0 +1,290 @@
+//! Marks that are on the board before the first move: handicaps and custom starting positions.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hobogo::{Board, Coord, HobogoError, Player};
+
+/// The most handicap marks we have standard points for.
+pub const MAX_HANDICAP: usize = 9;
+
+/// A starting position: which player has a mark where before anyone moves.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Setup {
+    /// In the order they are placed.
+    marks: Vec<(Coord, Player)>,
+}
+
+impl Setup {
+    /// The marks already on a board.
+    pub fn from_board(board: &Board) -> Setup {
+        Setup {
+            marks: board
+                .coords()
+                .filter_map(|c| board.at(c).map(|player| (c, player)))
+                .collect(),
+        }
+    }
+
+    /// `count` marks for `player` on the standard handicap points of the board.
+    /// See [`handicap_points`].
+    pub fn handicap(board: &Board, player: Player, count: usize) -> Setup {
+        Setup {
+            marks: handicap_points(board, count)
+                .into_iter()
+                .map(|c| (c, player))
+                .collect(),
+        }
+    }
+
+    pub fn marks(&self) -> &[(Coord, Player)] {
+        &self.marks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    pub fn add(&mut self, c: Coord, player: Player) {
+        self.marks.push((c, player));
+    }
+
+    /// Put all the marks on the board, without checking the rules.
+    ///
+    /// Fails on the first mark that is outside the board, in an occupied cell
+    /// or for a player that doesn't exist, together with that mark.
+    pub fn place_on(&self, board: &mut Board) -> Result<(), (Coord, HobogoError)> {
+        for &(c, player) in &self.marks {
+            board.try_set(c, player).map_err(|err| (c, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where to put `count` handicap marks, like the star points in go:
+/// first the corners, then the middle of the sides, with the center for odd counts.
+///
+/// The points are on the third line from the edge (the fourth on boards of 13 or more),
+/// in the middle layer. Points that are blocked by the map are skipped,
+/// as is everything after the first [`MAX_HANDICAP`].
+pub fn handicap_points(board: &Board, count: usize) -> Vec<Coord> {
+    let line = |size: i32| {
+        if size >= 13 {
+            3
+        } else if size >= 7 {
+            2
+        } else {
+            size / 4
+        }
+    };
+    let (left, top) = (line(board.width), line(board.height));
+    let (right, bottom) = (board.width - 1 - left, board.height - 1 - top);
+    let (center_x, center_y) = ((board.width - 1) / 2, (board.height - 1) / 2);
+
+    let corners = [(right, top), (left, bottom), (right, bottom), (left, top)];
+    let sides = [
+        (left, center_y),
+        (right, center_y),
+        (center_x, top),
+        (center_x, bottom),
+    ];
+    let center = (center_x, center_y);
+
+    let count = count.min(MAX_HANDICAP);
+    let mut points: Vec<(i32, i32)> = if count <= 4 {
+        corners[..count].to_vec()
+    } else {
+        let num_sides = (count - 4) / 2 * 2;
+        let mut points = corners.to_vec();
+        points.extend(&sides[..num_sides]);
+        if count % 2 == 1 {
+            points.push(center);
+        }
+        points
+    };
+    // Small boards have fewer distinct points:
+    let mut seen = vec![];
+    points.retain(|point| {
+        let new = !seen.contains(point);
+        seen.push(*point);
+        new
+    });
+
+    let z = board.depth / 2;
+    points
+        .into_iter()
+        .map(|(x, y)| Coord { x, y, z })
+        .filter(|&c| board.contains(c))
+        .collect()
+}
+
+/// Written as the marks separated by commas, each as a cell and the player, e.g. `C3=1,G7=1`.
+/// The empty setup is written as `-`.
+impl fmt::Display for Setup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("-");
+        }
+        let marks: Vec<String> = self
+            .marks
+            .iter()
+            .map(|(c, player)| format!("{}={}", c, player))
+            .collect();
+        f.write_str(&marks.join(","))
+    }
+}
+
+/// Why a [`Setup`] could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseSetupError(String);
+
+impl fmt::Display for ParseSetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bad setup: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSetupError {}
+
+impl FromStr for Setup {
+    type Err = ParseSetupError;
+
+    fn from_str(s: &str) -> Result<Setup, ParseSetupError> {
+        let mut setup = Setup::default();
+        if s.trim() == "-" {
+            return Ok(setup);
+        }
+        for mark in s.split(',').map(str::trim).filter(|mark| !mark.is_empty()) {
+            let (cell, player) = match mark.find('=') {
+                Some(equals) => (&mark[..equals], &mark[equals + 1..]),
+                None => {
+                    return Err(ParseSetupError(format!(
+                        "expected e.g. 'C3=1', got '{}'",
+                        mark
+                    )))
+                }
+            };
+            let c: Coord = cell
+                .trim()
+                .parse()
+                .map_err(|err| ParseSetupError(format!("{}", err)))?;
+            let player: Player = player
+                .trim()
+                .parse()
+                .map_err(|_| ParseSetupError(format!("'{}' is not a player", player.trim())))?;
+            setup.add(c, player);
+        }
+        Ok(setup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Map;
+
+    fn points(board: &Board, count: usize) -> Vec<(i32, i32)> {
+        handicap_points(board, count)
+            .into_iter()
+            .map(|c| (c.x, c.y))
+            .collect()
+    }
+
+    #[test]
+    fn corners_then_sides_then_center() {
+        let board = Board::new(9, 9, 2);
+        assert_eq!(points(&board, 0), vec![]);
+        assert_eq!(points(&board, 2), vec![(6, 2), (2, 6)]);
+        assert_eq!(points(&board, 4), vec![(6, 2), (2, 6), (6, 6), (2, 2)]);
+        assert_eq!(
+            points(&board, 5),
+            vec![(6, 2), (2, 6), (6, 6), (2, 2), (4, 4)]
+        );
+        assert_eq!(
+            points(&board, 6),
+            vec![(6, 2), (2, 6), (6, 6), (2, 2), (2, 4), (6, 4)]
+        );
+        assert_eq!(points(&board, 9).len(), 9);
+        assert_eq!(points(&board, 9)[8], (4, 4));
+        assert_eq!(points(&board, 100), points(&board, MAX_HANDICAP));
+
+        // The fourth line on big boards:
+        assert_eq!(points(&Board::new(19, 13, 2), 1), vec![(15, 3)]);
+    }
+
+    #[test]
+    fn points_are_distinct_and_on_the_board() {
+        for width in 1..16 {
+            for height in 1..16 {
+                let board = Board::new(width, height, 2);
+                for count in 0..=MAX_HANDICAP + 1 {
+                    let points = handicap_points(&board, count);
+                    assert!(points.len() <= count.min(MAX_HANDICAP));
+                    for (i, &c) in points.iter().enumerate() {
+                        assert!(board.contains(c) && !points[..i].contains(&c));
+                    }
+                    if width >= 7 && height >= 7 {
+                        assert_eq!(points.len(), count.min(MAX_HANDICAP));
+                    }
+                }
+            }
+        }
+
+        // Everything is the same cell on a 1x1 board:
+        assert_eq!(points(&Board::new(1, 1, 2), MAX_HANDICAP), vec![(0, 0)]);
+        // An even width has no middle column, so some sides and the center coincide:
+        assert_eq!(points(&Board::new(2, 2, 2), MAX_HANDICAP).len(), 4);
+        assert_eq!(
+            points(&Board::new(8, 6, 2), 5),
+            vec![(5, 1), (2, 4), (5, 4), (2, 1), (3, 2)]
+        );
+    }
+
+    #[test]
+    fn blocked_points_are_skipped() {
+        let mut map = Map::full(9, 9);
+        map.block(Coord::new(6, 2));
+        let board = Board::from_map(&map, 2);
+        assert_eq!(points(&board, 1), vec![]);
+        assert_eq!(points(&board, 2), vec![(2, 6)]);
+
+        let layered = Board::new(9, 9, 2).with_layers(3);
+        assert!(handicap_points(&layered, 4).iter().all(|c| c.z == 1));
+    }
+
+    #[test]
+    fn marks_are_placed_in_order() {
+        let cell = |name: &str| name.parse::<Coord>().unwrap();
+        let setup: Setup = "B1=1, A1=0, B1=0, C1=1".parse().unwrap();
+        assert_eq!(
+            setup.marks(),
+            &[
+                (cell("B1"), 1),
+                (cell("A1"), 0),
+                (cell("B1"), 0),
+                (cell("C1"), 1)
+            ]
+        );
+        assert_eq!(setup.to_string(), "B1=1,A1=0,B1=0,C1=1");
+
+        // The second mark in B1 is the one that fails, after the ones before it are placed:
+        let mut board = Board::new(3, 1, 2);
+        assert_eq!(
+            setup.place_on(&mut board),
+            Err((cell("B1"), HobogoError::Occupied(cell("B1"))))
+        );
+        assert_eq!(board.at(cell("A1")), Some(0));
+        assert_eq!(board.at(cell("B1")), Some(1));
+        assert_eq!(board.at(cell("C1")), None);
+
+        let setup: Setup = "A1=0,C1=1".parse().unwrap();
+        let mut board = Board::new(3, 1, 2);
+        setup.place_on(&mut board).unwrap();
+        assert_eq!(Setup::from_board(&board), setup);
+        assert_eq!("-".parse(), Ok(Setup::default()));
+        assert!("A1".parse::<Setup>().is_err());
+        assert!("A1=x".parse::<Setup>().is_err());
+    }
+}
@@ -0,0 +1,424 @@
+//! Exact search for small positions: alpha-beta with a transposition table.
+//!
+//! Unlike [`crate::mcts`], which only estimates, the solver plays every line to the very end,
+//! so it is only feasible once few free cells are left, or on tiny boards like 5x5.
+//!
+//! Because it looks all the way to the end, it can also check the heuristic early endings
+//! (e.g. [`crate::rules::RuleSet::end_when_settled`]): solve the same position with those rules
+//! turned off, and the result should not change.
+
+use std::collections::HashMap;
+
+use crate::ai::now_sec;
+use crate::hobogo::Influence;
+use crate::mcts::{Action, Game, GameState};
+use crate::teams::Team;
+
+/// The game-theoretic value of a position, and how to get it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Solution {
+    /// How many points the team of the player to move ends up ahead of the best other team
+    /// (negative if behind) when everyone plays perfectly.
+    ///
+    /// With more than two teams, the other teams are assumed to gang up on the team to move.
+    pub value: i32,
+
+    /// An optimal action, or `None` if the game is already over.
+    pub best_action: Option<Action>,
+}
+
+impl Solution {
+    pub fn is_win(&self) -> bool {
+        self.value > 0
+    }
+
+    pub fn is_tie(&self) -> bool {
+        self.value == 0
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Bound {
+    Exact,
+    /// The value is at least this.
+    Lower,
+    /// The value is at most this.
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    value: i32,
+    bound: Bound,
+    best_action: Option<Action>,
+}
+
+/// Remembers the positions it has solved, so keep it around when solving related positions
+/// for the same team.
+pub struct Solver {
+    /// Keyed by [`GameState::zobrist`]. All values are for `team`.
+    table: HashMap<u64, Entry>,
+    team: Team,
+    max_nodes: usize,
+    /// In seconds, see [`now_sec`].
+    deadline: Option<f64>,
+    num_nodes: usize,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver::new()
+    }
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Solver {
+            table: HashMap::new(),
+            team: 0,
+            max_nodes: usize::MAX,
+            deadline: None,
+            num_nodes: 0,
+        }
+    }
+
+    /// Give up after visiting this many positions in one [`Solver::solve`].
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    /// Give up once [`now_sec`] reaches `deadline`.
+    pub fn with_deadline(mut self, deadline: f64) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// How many positions the last [`Solver::solve`] visited.
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes
+    }
+
+    /// Search to the end of the game.
+    /// `None` if that takes more than the maximum number of positions, or goes past the deadline.
+    pub fn solve(&mut self, state: &GameState) -> Option<Solution> {
+        let team = state.board.teams().team_of(state.next_player);
+        if team != self.team {
+            self.table.clear();
+            self.team = team;
+        }
+        self.num_nodes = 0;
+        let (value, best_action) = self.search(state, i32::MIN, i32::MAX)?;
+        Some(Solution { value, best_action })
+    }
+
+    /// Fail-soft alpha-beta, returning the value and the best action.
+    /// `None` if we ran out of nodes.
+    fn search(
+        &mut self,
+        state: &GameState,
+        mut alpha: i32,
+        mut beta: i32,
+    ) -> Option<(i32, Option<Action>)> {
+        self.num_nodes += 1;
+        if self.num_nodes > self.max_nodes {
+            return None;
+        }
+        // Checking the clock is slow compared to a node, so only do it every now and then:
+        if self.num_nodes.is_multiple_of(64)
+            && self.deadline.is_some_and(|deadline| now_sec() >= deadline)
+        {
+            return None;
+        }
+
+        if state.board.is_game_over() {
+            return Some((self.margin(state), None));
+        }
+
+        // A swap depends on how we got here, which the hash doesn't know about:
+        let cacheable =
+            state.swappable.is_none() && !(state.num_actions == 0 && state.board.rules().swap);
+        let key = state.zobrist();
+        let mut tt_action = None;
+        if cacheable {
+            if let Some(entry) = self.table.get(&key) {
+                match entry.bound {
+                    Bound::Exact => return Some((entry.value, entry.best_action)),
+                    Bound::Lower => alpha = alpha.max(entry.value),
+                    Bound::Upper => beta = beta.min(entry.value),
+                }
+                if alpha >= beta {
+                    return Some((entry.value, entry.best_action));
+                }
+                tt_action = entry.best_action;
+            }
+        }
+
+        let (original_alpha, original_beta) = (alpha, beta);
+        let maximizing = state.board.teams().team_of(state.next_player) == self.team;
+        let mut best_value = if maximizing { i32::MIN } else { i32::MAX };
+        let mut best_action = None;
+
+        for action in self.ordered_actions(state, tt_action) {
+            let mut child = state.clone();
+            child.take_action(&action);
+            let (value, _) = self.search(&child, alpha, beta)?;
+            if maximizing {
+                if value > best_value {
+                    best_value = value;
+                    best_action = Some(action);
+                }
+                alpha = alpha.max(value);
+            } else {
+                if value < best_value {
+                    best_value = value;
+                    best_action = Some(action);
+                }
+                beta = beta.min(value);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        if cacheable {
+            let bound = if best_value <= original_alpha {
+                Bound::Upper
+            } else if best_value >= original_beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.table.insert(
+                key,
+                Entry {
+                    value: best_value,
+                    bound,
+                    best_action,
+                },
+            );
+        }
+
+        Some((best_value, best_action))
+    }
+
+    /// Our points minus the most points of any other team.
+    fn margin(&self, state: &GameState) -> i32 {
+        let points = state.board.points();
+        let ours = points[self.team as usize] as i32;
+        let best_other = points
+            .iter()
+            .enumerate()
+            .filter(|&(team, _)| team != self.team as usize)
+            .map(|(_, &points)| points as i32)
+            .max()
+            .unwrap_or(0);
+        ours - best_other
+    }
+
+    /// The best action from the table first, then contested cells,
+    /// and cells that are already ours last.
+    fn ordered_actions(&self, state: &GameState, first: Option<Action>) -> Vec<Action> {
+        let board = &state.board;
+        let teams = board.teams();
+        let team = teams.team_of(state.next_player);
+        let priority = |action: &Action| -> u8 {
+            if Some(*action) == first {
+                return 0;
+            }
+            match action {
+                Action::Move(c) => match board.influence(*c) {
+                    Influence::Tied(..) => 1,
+                    Influence::Claimed(claimer, _) if claimer == team => 2,
+                    Influence::Claimed(..) => 3,
+                    Influence::Ruled(ruler) if ruler == team => 5,
+                    Influence::Ruled(_) | Influence::Occupied(_) => 4,
+                },
+                Action::Swap => 1,
+                Action::Pass => 6,
+            }
+        };
+        let mut actions = state.available_actions();
+        actions.sort_by_key(priority);
+        actions
+    }
+}
+
+/// Solve for the player to move. `None` if that takes more than `max_nodes` positions.
+pub fn solve(state: &GameState, max_nodes: usize) -> Option<Solution> {
+    Solver::new().with_max_nodes(max_nodes).solve(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::ai::AiRng;
+    use crate::hobogo::{Board, Coord};
+    use crate::rules::RuleSet;
+
+    fn state(board: Board, next_player: u8) -> GameState {
+        GameState {
+            next_player,
+            board,
+            num_actions: 0,
+            swappable: None,
+        }
+    }
+
+    /// Minimax without any tricks, scored by [`Board::game_result`]:
+    /// `team` maximizes their margin, everyone else minimizes it.
+    fn minimax(state: &GameState, team: Team) -> i32 {
+        if let Some(result) = state.board.game_result() {
+            assert!(state.board.is_game_over());
+            let ours = result.points[team as usize] as i32;
+            let best_other = (0..result.points.len())
+                .filter(|&other| other != team as usize)
+                .map(|other| result.points[other] as i32)
+                .max()
+                .unwrap();
+            return ours - best_other;
+        }
+        let values = state.available_actions().into_iter().map(|action| {
+            let mut child = state.clone();
+            child.take_action(&action);
+            minimax(&child, team)
+        });
+        if state.board.teams().team_of(state.next_player) == team {
+            values.max().unwrap()
+        } else {
+            values.min().unwrap()
+        }
+    }
+
+    #[test]
+    fn agrees_with_minimax() {
+        let mut rng = AiRng::seed_from_u64(4);
+        let mut num_checked = 0;
+        while num_checked < 60 {
+            let (width, height) = (rng.gen_range(2, 5), rng.gen_range(2, 4));
+            let num_players = rng.gen_range(2, 4);
+            let mut state = state(Board::new(width, height, num_players), 0);
+            while state.board.num_free_cells() > 6 && !state.board.is_game_over() {
+                let actions = state.available_actions();
+                state.take_action(&actions[rng.gen_range(0, actions.len())]);
+            }
+            if state.board.is_game_over() {
+                continue;
+            }
+
+            let team = state.board.teams().team_of(state.next_player);
+            let solution = solve(&state, usize::MAX).unwrap();
+            assert_eq!(solution.value, minimax(&state, team), "{:#}", state.board);
+
+            let mut child = state.clone();
+            child.take_action(&solution.best_action.unwrap());
+            assert_eq!(minimax(&child, team), solution.value, "{:#}", state.board);
+            num_checked += 1;
+        }
+    }
+
+    /// The same rules, but without the early endings: the game goes on until it runs out of moves.
+    fn played_out(rules: RuleSet) -> RuleSet {
+        RuleSet {
+            end_when_settled: false,
+            end_when_all_ruled: false,
+            end_on_unbeatable_lead: false,
+            ..rules
+        }
+    }
+
+    #[test]
+    fn early_endings_dont_change_the_outcome() {
+        let variants = [
+            "standard",
+            "all-ruled-ends",
+            "lead-ends",
+            "margin=2",
+            "ties-block",
+            "margin=3,ties-block,all-ruled-ends",
+            "stones-only",
+            "stones-only,all-ruled-ends,lead-ends",
+        ];
+        let mut rng = AiRng::seed_from_u64(5);
+        for variant in &variants {
+            let rules: RuleSet = variant.parse().unwrap();
+            let mut num_checked = 0;
+            while num_checked < 30 {
+                let (width, height) = (rng.gen_range(2, 5), rng.gen_range(2, 4));
+                let num_players = rng.gen_range(2, 4);
+                let board = Board::new(width, height, num_players).with_rules(played_out(rules));
+                let mut state = state(board, 0);
+                while state.board.num_free_cells() > 7 && !state.board.is_game_over() {
+                    let actions = state.available_actions();
+                    state.take_action(&actions[rng.gen_range(0, actions.len())]);
+                }
+                if state.board.is_game_over() {
+                    continue;
+                }
+
+                let played_out_value = solve(&state, usize::MAX).unwrap().value;
+                state.board = state.board.with_rules(rules);
+                let value = solve(&state, usize::MAX).unwrap().value;
+                if rules.end_on_unbeatable_lead {
+                    // It ends as soon as the winner is known, but not how much they win by:
+                    assert_eq!(
+                        value.signum(),
+                        played_out_value.signum(),
+                        "{}: {} vs {} on{:?}",
+                        variant,
+                        value,
+                        played_out_value,
+                        state.board
+                    );
+                } else {
+                    assert_eq!(value, played_out_value, "{} on{:?}", variant, state.board);
+                }
+                num_checked += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn finds_a_forced_win() {
+        // X in the middle outnumbers O everywhere, so O never gets to move:
+        let state = state(Board::new(3, 1, 2), 0);
+        let solution = solve(&state, usize::MAX).unwrap();
+        assert!(solution.is_win());
+        assert_eq!(solution.value, 3);
+        assert_eq!(solution.best_action, Some(Action::Move(Coord::new(1, 0))));
+
+        // Anywhere else, and O gets a cell of their own:
+        let mut state = state;
+        state.take_action(&Action::Move(Coord::new(0, 0)));
+        assert_eq!(solve(&state, usize::MAX).unwrap().value, -1);
+    }
+
+    #[test]
+    fn solved_games_are_over() {
+        let mut board = Board::new(3, 1, 2);
+        board.set(Coord::new(1, 0), 0);
+        let state = state(board, 1);
+        assert!(state.board.is_game_over());
+        let result = state.board.game_result().unwrap();
+        let solution = solve(&state, usize::MAX).unwrap();
+        assert_eq!(solution.best_action, None);
+        assert_eq!(
+            solution.value,
+            result.points[1] as i32 - result.points[0] as i32
+        );
+    }
+
+    #[test]
+    fn gives_up() {
+        let state = state(Board::new(4, 4, 2), 0);
+        let mut solver = Solver::new().with_max_nodes(100);
+        assert_eq!(solver.solve(&state), None);
+        assert_eq!(solver.num_nodes(), 101);
+
+        let mut solver = Solver::new().with_deadline(now_sec() - 1.0);
+        assert_eq!(solver.solve(&state), None);
+        assert!(solver.num_nodes() <= 64);
+    }
+}
@@ -0,0 +1,223 @@
+//! Playing in teams.
+//!
+//! Teammates count as friends around each other's moves, claim cells together and share their points.
+//! Without teams every player is a team of their own, with the same number as the player.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hobogo::{Player, MAX_PLAYERS};
+
+/// Teams are numbered from 0.
+pub type Team = u8;
+
+/// Which team each player is on.
+///
+/// There are at least two teams, numbered from 0, and each has at least one player.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "Vec<Team>", into = "Vec<Team>")]
+pub struct Teams {
+    /// Indexed by player.
+    team_of: Vec<Team>,
+    num_teams: usize,
+}
+
+impl Teams {
+    /// Everyone for themselves.
+    pub fn solo(num_players: usize) -> Teams {
+        Teams {
+            team_of: (0..num_players).map(|player| player as Team).collect(),
+            num_teams: num_players,
+        }
+    }
+
+    /// Player `p` plays for team `p % num_teams`, so the teams take turns.
+    pub fn round_robin(num_players: usize, num_teams: usize) -> Teams {
+        assert!(
+            2 <= num_teams && num_teams <= num_players,
+            "Can't split {} players into {} teams",
+            num_players,
+            num_teams
+        );
+        Teams {
+            team_of: (0..num_players)
+                .map(|player| (player % num_teams) as Team)
+                .collect(),
+            num_teams,
+        }
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.team_of.len()
+    }
+
+    pub fn num_teams(&self) -> usize {
+        self.num_teams
+    }
+
+    pub fn team_of(&self, player: Player) -> Team {
+        self.team_of[player as usize]
+    }
+
+    /// The players of a team, in turn order.
+    pub fn members(&self, team: Team) -> impl Iterator<Item = Player> + '_ {
+        (0..self.num_players())
+            .map(|player| player as Player)
+            .filter(move |&player| self.team_of(player) == team)
+    }
+
+    /// The first player of the team, who represents it e.g. in diagrams.
+    pub fn leader(&self, team: Team) -> Player {
+        self.members(team).next().expect("Empty team")
+    }
+
+    /// Is everyone on a team of their own?
+    pub fn is_solo(&self) -> bool {
+        self.num_teams() == self.num_players()
+    }
+}
+
+/// Why some [`Teams`] are invalid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidTeams(String);
+
+impl fmt::Display for InvalidTeams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bad teams: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTeams {}
+
+impl TryFrom<Vec<Team>> for Teams {
+    type Error = InvalidTeams;
+
+    /// The team of each player.
+    fn try_from(team_of: Vec<Team>) -> Result<Teams, InvalidTeams> {
+        let num_teams = team_of.iter().max().map_or(0, |&team| team as usize + 1);
+        if team_of.len() > MAX_PLAYERS {
+            return Err(InvalidTeams(format!(
+                "{} players, but there can be at most {}",
+                team_of.len(),
+                MAX_PLAYERS
+            )));
+        }
+        let teams = Teams { team_of, num_teams };
+        if num_teams < 2 {
+            return Err(InvalidTeams("there must be at least two teams".to_owned()));
+        }
+        for team in 0..num_teams {
+            if teams.members(team as Team).next().is_none() {
+                return Err(InvalidTeams(format!("team {} has no players", team)));
+            }
+        }
+        Ok(teams)
+    }
+}
+
+impl From<Teams> for Vec<Team> {
+    fn from(teams: Teams) -> Vec<Team> {
+        teams.team_of
+    }
+}
+
+/// Written as the players of each team joined by `+`, with the teams separated by commas,
+/// e.g. `0+2,1+3`.
+impl fmt::Display for Teams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let teams: Vec<String> = (0..self.num_teams())
+            .map(|team| {
+                let members: Vec<String> = self
+                    .members(team as Team)
+                    .map(|player| player.to_string())
+                    .collect();
+                members.join("+")
+            })
+            .collect();
+        f.write_str(&teams.join(","))
+    }
+}
+
+impl FromStr for Teams {
+    type Err = InvalidTeams;
+
+    fn from_str(s: &str) -> Result<Teams, InvalidTeams> {
+        let mut team_of: Vec<Option<Team>> = vec![];
+        for (team, members) in s.split(',').enumerate() {
+            for member in members.split('+').map(str::trim) {
+                let player: Player = member
+                    .parse()
+                    .map_err(|_| InvalidTeams(format!("'{}' is not a player", member)))?;
+                let player = player as usize;
+                if team_of.len() <= player {
+                    team_of.resize(player + 1, None);
+                }
+                if team_of[player].replace(team as Team).is_some() {
+                    return Err(InvalidTeams(format!("player {} is on two teams", player)));
+                }
+            }
+        }
+        let team_of = team_of
+            .into_iter()
+            .enumerate()
+            .map(|(player, team)| {
+                team.ok_or_else(|| InvalidTeams(format!("player {} is on no team", player)))
+            })
+            .collect::<Result<Vec<Team>, InvalidTeams>>()?;
+        Teams::try_from(team_of)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hobogo::{Board, Influence};
+
+    #[test]
+    fn round_robin() {
+        let teams = Teams::round_robin(5, 2);
+        assert_eq!(teams.num_teams(), 2);
+        assert_eq!(teams.members(0).collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert_eq!(teams.members(1).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(teams.leader(1), 1);
+        assert_eq!(teams.team_of(4), 0);
+        assert!(!teams.is_solo());
+        assert!(Teams::solo(3).is_solo());
+        assert_eq!(Teams::round_robin(3, 3), Teams::solo(3));
+    }
+
+    #[test]
+    fn text_and_serde() {
+        let teams = Teams::round_robin(4, 2);
+        assert_eq!(teams.to_string(), "0+2,1+3");
+        assert_eq!(" 0 + 2 , 1+3".parse(), Ok(teams.clone()));
+        assert_eq!(serde_json::to_string(&teams).unwrap(), "[0,1,0,1]");
+        assert_eq!(serde_json::from_str::<Teams>("[0,1,0,1]").unwrap(), teams);
+
+        for bad in &["", "0+1", "0,0", "0,2", "0,x", "0,1+", "0,1,,2"] {
+            assert!(bad.parse::<Teams>().is_err(), "{}", bad);
+        }
+        for bad in &["[]", "[0,0]", "[0,2,0]", "[1,1,2]"] {
+            assert!(serde_json::from_str::<Teams>(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn teammates_are_friends() {
+        // X and A against O:
+        let teams: Teams = "0+2,1".parse().unwrap();
+        let board = "X O A\n. . .".parse::<Board>().unwrap().with_teams(teams);
+        let b2 = "B2".parse().unwrap();
+        match board.influence(b2) {
+            Influence::Claimed(team, lead) => assert_eq!((team, lead), (0, 1)),
+            influence => panic!("{:?}", influence),
+        }
+        assert!(board.is_valid_move(b2, 0));
+        assert!(board.is_valid_move(b2, 2));
+        assert!(!board.is_valid_move(b2, 1));
+        assert_eq!(board.points().len(), 2);
+    }
+}
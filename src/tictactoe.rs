@@ -0,0 +1,152 @@
+//! Tic-tac-toe, as a tiny [`Game`] with well known optimal play for checking [`crate::mcts`]:
+//! with perfect play every game is a draw, and nobody misses a win or a block.
+//!
+//! Only used in tests.
+
+use std::fmt;
+
+use crate::mcts::{Game, Score};
+
+const LINES: [[usize; 3]; 8] = [
+    [0, 1, 2],
+    [3, 4, 5],
+    [6, 7, 8],
+    [0, 3, 6],
+    [1, 4, 7],
+    [2, 5, 8],
+    [0, 4, 8],
+    [2, 4, 6],
+];
+
+/// The cells are numbered 0-8, row by row. Player 0 goes first.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TicTacToe {
+    cells: [Option<u8>; 9],
+    next_player: u8,
+}
+
+impl TicTacToe {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Play the given cells in order, starting with player 0.
+    pub fn from_moves(moves: &[usize]) -> Self {
+        let mut game = TicTacToe::new();
+        for cell in moves {
+            game.take_action(cell);
+        }
+        game
+    }
+
+    pub fn winner(&self) -> Option<u8> {
+        LINES.iter().find_map(|&[a, b, c]| match self.cells[a] {
+            Some(player) if self.cells[b] == Some(player) && self.cells[c] == Some(player) => {
+                Some(player)
+            }
+            _ => None,
+        })
+    }
+}
+
+impl Game for TicTacToe {
+    /// The cell to put a mark in.
+    type Action = usize;
+
+    fn num_players(&self) -> usize {
+        2
+    }
+
+    fn next_player(&self) -> usize {
+        self.next_player as usize
+    }
+
+    fn available_actions(&self) -> Vec<usize> {
+        if self.winner().is_some() {
+            return vec![];
+        }
+        (0..9).filter(|&cell| self.cells[cell].is_none()).collect()
+    }
+
+    fn take_action(&mut self, &cell: &usize) {
+        assert!(self.cells[cell].is_none(), "Cell {} is taken", cell);
+        self.cells[cell] = Some(self.next_player);
+        self.next_player = 1 - self.next_player;
+    }
+
+    fn score(&self) -> Score {
+        match self.winner() {
+            Some(winner) => (0..2)
+                .map(|player| if player == winner { 1.0 } else { 0.0 })
+                .collect(),
+            None => vec![0.5, 0.5],
+        }
+    }
+}
+
+impl fmt::Display for TicTacToe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.cells.chunks(3) {
+            let row: String = row
+                .iter()
+                .map(|cell| match cell {
+                    Some(0) => 'X',
+                    Some(_) => 'O',
+                    None => '.',
+                })
+                .collect();
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::ai::AiRng;
+    use crate::mcts::Mcts;
+
+    fn best_action(game: &TicTacToe, iterations: usize, seed: u64) -> usize {
+        let mut mcts = Mcts::new(game.clone());
+        let mut rng = AiRng::seed_from_u64(seed);
+        for _ in 0..iterations {
+            mcts.iterate(&mut rng);
+        }
+        *mcts.best_action().unwrap()
+    }
+
+    #[test]
+    fn takes_a_win_in_one() {
+        // X: 0 1 _    X to move wins with 2.
+        // O: 3 4 _
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4]);
+        for seed in 0..5 {
+            assert_eq!(best_action(&game, 2_000, seed), 2, "\n{}", game);
+        }
+    }
+
+    #[test]
+    fn blocks_a_win_in_one() {
+        // O threatens 3 4 5, and X has no win of their own, so X must take 5.
+        let game = TicTacToe::from_moves(&[0, 3, 8, 4]);
+        for seed in 0..5 {
+            assert_eq!(best_action(&game, 2_000, seed), 5, "\n{}", game);
+        }
+    }
+
+    #[test]
+    fn self_play_is_a_draw() {
+        for seed in 0..5 {
+            let mut game = TicTacToe::new();
+            while !game.available_actions().is_empty() {
+                let action = best_action(&game, 5_000, seed);
+                game.take_action(&action);
+            }
+            assert_eq!(game.winner(), None, "\n{}", game);
+            assert_eq!(game.score(), vec![0.5, 0.5]);
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Which cells are neighbors of which.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hobogo::{Coord, Symmetry};
+
+/// Which of the surrounding cells count as neighbors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Neighborhood {
+    /// Square cells, with all eight surrounding cells as neighbors.
+    Moore,
+
+    /// Square cells, with only the four cells sharing an edge as neighbors.
+    VonNeumann,
+
+    /// Hexagonal cells, with six neighbors each.
+    ///
+    /// The board is a parallelogram in axial coordinates:
+    /// each row is shifted half a cell to the right of the one above it,
+    /// so `(x + 1, y - 1)` and `(x - 1, y + 1)` are neighbors of `(x, y)`, but `(x + 1, y + 1)` is not.
+    Hex,
+}
+
+/// `(dx, dy, dz)`
+pub type Offset = (i32, i32, i32);
+
+const MOORE: &[Offset] = &[
+    (-1, -1, 0),
+    (0, -1, 0),
+    (1, -1, 0),
+    (-1, 0, 0),
+    (1, 0, 0),
+    (-1, 1, 0),
+    (0, 1, 0),
+    (1, 1, 0),
+];
+const VON_NEUMANN: &[Offset] = &[(0, -1, 0), (-1, 0, 0), (1, 0, 0), (0, 1, 0)];
+const HEX: &[Offset] = &[
+    (0, -1, 0),
+    (1, -1, 0),
+    (-1, 0, 0),
+    (1, 0, 0),
+    (-1, 1, 0),
+    (0, 1, 0),
+];
+
+// On layered boards you also neighbor the cell right above and below you,
+// and (except for von Neumann) their neighbors within their layers.
+
+const MOORE_LAYERED: &[Offset] = &[
+    (-1, -1, 0),
+    (0, -1, 0),
+    (1, -1, 0),
+    (-1, 0, 0),
+    (1, 0, 0),
+    (-1, 1, 0),
+    (0, 1, 0),
+    (1, 1, 0),
+    (-1, -1, -1),
+    (0, -1, -1),
+    (1, -1, -1),
+    (-1, 0, -1),
+    (0, 0, -1),
+    (1, 0, -1),
+    (-1, 1, -1),
+    (0, 1, -1),
+    (1, 1, -1),
+    (-1, -1, 1),
+    (0, -1, 1),
+    (1, -1, 1),
+    (-1, 0, 1),
+    (0, 0, 1),
+    (1, 0, 1),
+    (-1, 1, 1),
+    (0, 1, 1),
+    (1, 1, 1),
+];
+const VON_NEUMANN_LAYERED: &[Offset] = &[
+    (0, -1, 0),
+    (-1, 0, 0),
+    (1, 0, 0),
+    (0, 1, 0),
+    (0, 0, -1),
+    (0, 0, 1),
+];
+const HEX_LAYERED: &[Offset] = &[
+    (0, -1, 0),
+    (1, -1, 0),
+    (-1, 0, 0),
+    (1, 0, 0),
+    (-1, 1, 0),
+    (0, 1, 0),
+    (0, -1, -1),
+    (1, -1, -1),
+    (-1, 0, -1),
+    (0, 0, -1),
+    (1, 0, -1),
+    (-1, 1, -1),
+    (0, 1, -1),
+    (0, -1, 1),
+    (1, -1, 1),
+    (-1, 0, 1),
+    (0, 0, 1),
+    (1, 0, 1),
+    (-1, 1, 1),
+    (0, 1, 1),
+];
+
+/// The shape of the board: its neighborhood, and whether it wraps around at the edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Topology {
+    pub neighborhood: Neighborhood,
+
+    /// If true, the left edge is glued to the right edge and the top to the bottom (a torus).
+    /// The layers of a layered board never wrap around.
+    pub wrap: bool,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology::SQUARE
+    }
+}
+
+impl Topology {
+    /// Square cells with eight neighbors, bounded by the board edges.
+    pub const SQUARE: Topology = Topology {
+        neighborhood: Neighborhood::Moore,
+        wrap: false,
+    };
+
+    pub fn is_hex(&self) -> bool {
+        self.neighborhood == Neighborhood::Hex
+    }
+
+    /// How to step from a cell to each of its neighbors (not including the cell itself).
+    /// `layered` is for boards with more than one layer.
+    pub fn offsets(&self, layered: bool) -> &'static [Offset] {
+        match (self.neighborhood, layered) {
+            (Neighborhood::Moore, false) => MOORE,
+            (Neighborhood::VonNeumann, false) => VON_NEUMANN,
+            (Neighborhood::Hex, false) => HEX,
+            (Neighborhood::Moore, true) => MOORE_LAYERED,
+            (Neighborhood::VonNeumann, true) => VON_NEUMANN_LAYERED,
+            (Neighborhood::Hex, true) => HEX_LAYERED,
+        }
+    }
+
+    /// Take a step from `c` on a board of the given width, height and depth.
+    /// `None` if we fall off the edge.
+    pub fn step(
+        &self,
+        (width, height, depth): (i32, i32, i32),
+        c: Coord,
+        (dx, dy, dz): Offset,
+    ) -> Option<Coord> {
+        let (x, y, z) = (c.x + dx, c.y + dy, c.z + dz);
+        if z < 0 || depth <= z {
+            None
+        } else if self.wrap {
+            Some(Coord {
+                x: x.rem_euclid(width),
+                y: y.rem_euclid(height),
+                z,
+            })
+        } else if 0 <= x && x < width && 0 <= y && y < height {
+            Some(Coord { x, y, z })
+        } else {
+            None
+        }
+    }
+
+    /// Take the step `offsets[i]` from `c`, like [`Topology::step`],
+    /// but `None` if that leads back to `c` or to a cell one of the earlier `offsets` already leads to.
+    ///
+    /// That only happens on boards that wrap around and are less than three cells wide or high,
+    /// where e.g. the cells to the left and to the right are the same cell.
+    /// A neighbor counts once, no matter how many ways there are to get to it.
+    pub fn distinct_step(
+        &self,
+        board_size: (i32, i32, i32),
+        c: Coord,
+        offsets: &[Offset],
+        i: usize,
+    ) -> Option<Coord> {
+        let to = self.step(board_size, c, offsets[i])?;
+        let (width, height, _) = board_size;
+        if self.wrap && (width < 3 || height < 3) {
+            let repeated = to == c
+                || offsets[..i]
+                    .iter()
+                    .any(|&offset| self.step(board_size, c, offset) == Some(to));
+            if repeated {
+                return None;
+            }
+        }
+        Some(to)
+    }
+
+    /// Does the symmetry map neighbors onto neighbors?
+    pub fn allows(&self, symmetry: Symmetry) -> bool {
+        let offsets = self.offsets(true);
+        let origin = symmetry.transform(Coord::new(0, 0), 1, 1);
+        offsets.iter().all(|&(dx, dy, dz)| {
+            let moved = symmetry.transform(
+                Coord {
+                    x: dx,
+                    y: dy,
+                    z: dz,
+                },
+                1,
+                1,
+            );
+            offsets.contains(&(moved.x - origin.x, moved.y - origin.y, dz))
+        })
+    }
+}
+
+/// Written as `square`, `square4` or `hex`, followed by `,torus` if it wraps around.
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.neighborhood {
+            Neighborhood::Moore => "square",
+            Neighborhood::VonNeumann => "square4",
+            Neighborhood::Hex => "hex",
+        })?;
+        if self.wrap {
+            f.write_str(",torus")?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a [`Topology`] could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTopologyError(String);
+
+impl fmt::Display for ParseTopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown topology '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseTopologyError {}
+
+impl FromStr for Topology {
+    type Err = ParseTopologyError;
+
+    fn from_str(s: &str) -> Result<Topology, ParseTopologyError> {
+        let mut parts = s.split(',').map(str::trim);
+        let neighborhood = match parts.next() {
+            Some("square") => Neighborhood::Moore,
+            Some("square4") => Neighborhood::VonNeumann,
+            Some("hex") => Neighborhood::Hex,
+            _ => return Err(ParseTopologyError(s.to_owned())),
+        };
+        let wrap = match parts.next() {
+            None => false,
+            Some("torus") => true,
+            Some(_) => return Err(ParseTopologyError(s.to_owned())),
+        };
+        if parts.next().is_some() {
+            return Err(ParseTopologyError(s.to_owned()));
+        }
+        Ok(Topology { neighborhood, wrap })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Topology; 6] = [
+        Topology::SQUARE,
+        Topology {
+            neighborhood: Neighborhood::VonNeumann,
+            wrap: false,
+        },
+        Topology {
+            neighborhood: Neighborhood::Hex,
+            wrap: false,
+        },
+        Topology {
+            neighborhood: Neighborhood::Moore,
+            wrap: true,
+        },
+        Topology {
+            neighborhood: Neighborhood::VonNeumann,
+            wrap: true,
+        },
+        Topology {
+            neighborhood: Neighborhood::Hex,
+            wrap: true,
+        },
+    ];
+
+    fn neighbors(topology: Topology, board_size: (i32, i32, i32), c: Coord) -> Vec<Coord> {
+        let offsets = topology.offsets(board_size.2 > 1);
+        (0..offsets.len())
+            .filter_map(|i| topology.distinct_step(board_size, c, offsets, i))
+            .collect()
+    }
+
+    #[test]
+    fn number_of_neighbors() {
+        let middle = Coord { x: 2, y: 2, z: 1 };
+        for &topology in &ALL {
+            let flat = neighbors(topology, (5, 5, 1), Coord::new(2, 2)).len();
+            let layered = neighbors(topology, (5, 5, 3), middle).len();
+            let expected = match topology.neighborhood {
+                Neighborhood::Moore => (8, 26),
+                Neighborhood::VonNeumann => (4, 6),
+                Neighborhood::Hex => (6, 20),
+            };
+            assert_eq!((flat, layered), expected, "{}", topology);
+
+            let corner = neighbors(topology, (5, 5, 1), Coord::new(0, 0)).len();
+            assert_eq!(corner == flat, topology.wrap, "{}", topology);
+        }
+    }
+
+    #[test]
+    fn neighbors_are_mutual_and_distinct() {
+        for &topology in &ALL {
+            for width in 1..5 {
+                for height in 1..5 {
+                    for depth in 1..3 {
+                        let board_size = (width, height, depth);
+                        for z in 0..depth {
+                            for y in 0..height {
+                                for x in 0..width {
+                                    let c = Coord { x, y, z };
+                                    let around = neighbors(topology, board_size, c);
+                                    for (i, &n) in around.iter().enumerate() {
+                                        assert!(n != c && !around[..i].contains(&n));
+                                        assert!(
+                                            neighbors(topology, board_size, n).contains(&c),
+                                            "{} on {:?} {}: {} is next to {}, but not the other way around",
+                                            topology,
+                                            board_size,
+                                            c,
+                                            n,
+                                            c
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_tori_wrap_around() {
+        let size = (4, 3, 2);
+        let c = Coord { x: 3, y: 0, z: 1 };
+        assert_eq!(Topology::SQUARE.step(size, c, (1, -1, 0)), None);
+        assert_eq!(
+            Topology::SQUARE.step(size, c, (-1, 1, -1)),
+            Some(Coord { x: 2, y: 1, z: 0 })
+        );
+        let torus = Topology {
+            neighborhood: Neighborhood::Moore,
+            wrap: true,
+        };
+        assert_eq!(
+            torus.step(size, c, (1, -1, 0)),
+            Some(Coord { x: 0, y: 2, z: 1 })
+        );
+        assert_eq!(torus.step(size, c, (0, 0, 1)), None);
+    }
+
+    #[test]
+    fn hex_boards_have_fewer_symmetries() {
+        for &topology in &ALL {
+            let allowed: Vec<Symmetry> = Symmetry::ALL
+                .iter()
+                .copied()
+                .filter(|&symmetry| topology.allows(symmetry))
+                .collect();
+            if topology.is_hex() {
+                assert_eq!(
+                    allowed,
+                    vec![
+                        Symmetry::Identity,
+                        Symmetry::Rotate180,
+                        Symmetry::Transpose,
+                        Symmetry::AntiTranspose
+                    ]
+                );
+            } else {
+                assert_eq!(allowed, Symmetry::ALL.to_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        for &topology in &ALL {
+            assert_eq!(topology.to_string().parse(), Ok(topology));
+        }
+        assert_eq!(
+            " hex , torus ".parse::<Topology>().map(|t| t.wrap),
+            Ok(true)
+        );
+        for bad in &["", "circle", "torus", "hex,", "hex,torus,torus"] {
+            assert!(bad.parse::<Topology>().is_err(), "{}", bad);
+        }
+    }
+}